@@ -0,0 +1,235 @@
+use bigdecimal::BigDecimal;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use order_book::models::{Order, OrderBook, OrderType, Side, TimeInForce, TriggerBy};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/**
+ * resting orders per side when the book is seeded
+ */
+const RESTING_ORDERS: usize = 100_000;
+
+/**
+ * distinct price levels the resting orders are spread over
+ */
+const PRICE_LEVELS: usize = 1_000;
+
+fn order(side: Side, order_type: OrderType, price: u64, quantity: u64) -> Order {
+    let now = chrono::Utc::now();
+    Order {
+        id: Uuid::new_v4(),
+        user_id: Uuid::new_v4(),
+        symbol: "BTC-PERP".to_string(),
+        side,
+        order_type,
+        price: BigDecimal::from(price),
+        trigger_price: None,
+        trigger_by: TriggerBy::LastPrice,
+        quantity: BigDecimal::from(quantity),
+        filled_quantity: BigDecimal::from(0),
+        display_quantity: None,
+        leverage: None,
+        time_in_force: TimeInForce::GTC,
+        post_only: None,
+        reduce_only: false,
+        self_trade_prevention: None,
+        created_at: now,
+        updated_at: now,
+        sequence: 0,
+        position_side: None,
+    }
+}
+
+/**
+ * bids at 10_000 and below, asks at 10_001 and above
+ */
+fn seed_orders() -> (Vec<Order>, Vec<Order>) {
+    let bids = (0..RESTING_ORDERS)
+        .map(|i| order(Side::Buy, OrderType::Limit, 10_000 - (i % PRICE_LEVELS) as u64, 1))
+        .collect();
+    let asks = (0..RESTING_ORDERS)
+        .map(|i| order(Side::Sell, OrderType::Limit, 10_001 + (i % PRICE_LEVELS) as u64, 1))
+        .collect();
+    (bids, asks)
+}
+
+fn seeded_book() -> OrderBook {
+    let (bids, asks) = seed_orders();
+    let mut book = OrderBook::new("BTC-PERP".to_string());
+    for o in bids.into_iter().chain(asks) {
+        book.add_order(o).unwrap();
+    }
+    book
+}
+
+/**
+ * the previous sorted-Vec book, kept here only as a baseline to compare against
+ */
+struct VecBook {
+    bids: Vec<Order>,
+    asks: Vec<Order>,
+}
+
+impl VecBook {
+    fn seeded() -> Self {
+        let (mut bids, mut asks) = seed_orders();
+        bids.sort_by(|a, b| b.price.cmp(&a.price));
+        asks.sort_by(|a, b| a.price.cmp(&b.price));
+        VecBook { bids, asks }
+    }
+
+    fn add_buy(&mut self, mut order: Order) {
+        let mut remaining_quantity = order.quantity.clone();
+        for ask in self.asks.iter_mut() {
+            if order.order_type == OrderType::Limit && ask.price > order.price {
+                break;
+            }
+            let fill_quantity = ask.remaining_quantity().min(remaining_quantity.clone());
+            ask.filled_quantity += &fill_quantity;
+            order.filled_quantity += &fill_quantity;
+            remaining_quantity -= fill_quantity;
+            if remaining_quantity <= BigDecimal::from(0) {
+                break;
+            }
+        }
+        self.asks.retain(|o| o.filled_quantity < o.quantity);
+        if remaining_quantity > BigDecimal::from(0) && order.order_type == OrderType::Limit {
+            self.bids.push(order);
+            self.bids.sort_by(|a, b| b.price.cmp(&a.price));
+        }
+    }
+
+    fn refill_asks(&mut self, count: usize) {
+        for _ in 0..count {
+            self.asks.push(order(Side::Sell, OrderType::Limit, 10_001, 1));
+        }
+        self.asks.sort_by(|a, b| a.price.cmp(&b.price));
+    }
+
+    fn cancel_bid(&mut self, order_id: Uuid) {
+        if let Some(pos) = self.bids.iter().position(|o| o.id == order_id) {
+            self.bids.remove(pos);
+        }
+    }
+}
+
+fn bench_insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert_resting_100k");
+    group.sample_size(10);
+
+    // each resting insert is cancelled again off the clock so the book stays at size
+    group.bench_function("price_levels", |b| {
+        b.iter_custom(|iters| {
+            let mut book = seeded_book();
+            let mut elapsed = Duration::ZERO;
+            for _ in 0..iters {
+                let bid = order(Side::Buy, OrderType::Limit, 9_500, 1);
+                let order_id = bid.id;
+                let start = Instant::now();
+                book.add_order(black_box(bid)).unwrap();
+                elapsed += start.elapsed();
+                book.cancel_order(order_id).unwrap();
+            }
+            elapsed
+        })
+    });
+    group.bench_function("sorted_vec", |b| {
+        b.iter_custom(|iters| {
+            let mut book = VecBook::seeded();
+            let mut elapsed = Duration::ZERO;
+            for _ in 0..iters {
+                let bid = order(Side::Buy, OrderType::Limit, 9_500, 1);
+                let order_id = bid.id;
+                let start = Instant::now();
+                book.add_buy(black_box(bid));
+                elapsed += start.elapsed();
+                book.cancel_bid(order_id);
+            }
+            elapsed
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_match(c: &mut Criterion) {
+    let mut group = c.benchmark_group("market_sweep_100k");
+    group.sample_size(10);
+
+    // takes out the best 50 resting asks, then tops the book back up off the clock
+    group.bench_function("price_levels", |b| {
+        b.iter_custom(|iters| {
+            let mut book = seeded_book();
+            let mut elapsed = Duration::ZERO;
+            for _ in 0..iters {
+                let taker = order(Side::Buy, OrderType::Market, 0, 50);
+                let start = Instant::now();
+                book.add_order(black_box(taker)).unwrap();
+                elapsed += start.elapsed();
+                for _ in 0..50 {
+                    book.add_order(order(Side::Sell, OrderType::Limit, 10_001, 1)).unwrap();
+                }
+            }
+            elapsed
+        })
+    });
+    group.bench_function("sorted_vec", |b| {
+        b.iter_custom(|iters| {
+            let mut book = VecBook::seeded();
+            let mut elapsed = Duration::ZERO;
+            for _ in 0..iters {
+                let taker = order(Side::Buy, OrderType::Market, 0, 50);
+                let start = Instant::now();
+                book.add_buy(black_box(taker));
+                elapsed += start.elapsed();
+                book.refill_asks(50);
+            }
+            elapsed
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_cancel(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cancel_deep_100k");
+    group.sample_size(10);
+
+    // the order furthest from the top of the book is the worst case for a scan,
+    // a replacement is posted at the back of the deepest level off the clock
+    group.bench_function("price_levels", |b| {
+        b.iter_custom(|iters| {
+            let mut book = seeded_book();
+            let mut elapsed = Duration::ZERO;
+            for _ in 0..iters {
+                let bid = order(Side::Buy, OrderType::Limit, 9_001, 1);
+                let order_id = bid.id;
+                book.add_order(bid).unwrap();
+                let start = Instant::now();
+                book.cancel_order(black_box(order_id)).unwrap();
+                elapsed += start.elapsed();
+            }
+            elapsed
+        })
+    });
+    group.bench_function("sorted_vec", |b| {
+        b.iter_custom(|iters| {
+            let mut book = VecBook::seeded();
+            let mut elapsed = Duration::ZERO;
+            for _ in 0..iters {
+                let bid = order(Side::Buy, OrderType::Limit, 9_001, 1);
+                let order_id = bid.id;
+                book.add_buy(bid);
+                let start = Instant::now();
+                book.cancel_bid(black_box(order_id));
+                elapsed += start.elapsed();
+            }
+            elapsed
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_insert, bench_match, bench_cancel);
+criterion_main!(benches);
@@ -1,47 +1,299 @@
-use crate::models::{Account, Position, Side, Order, OrderError, PositionType, MarginType};
+use crate::models::{
+    Account, Position, Side, Order, OrderError, OrderHold, PnlSummary, PositionType, MarginType, PositionMode, PositionSide,
+};
+use crate::ledger::Balances;
 use crate::margin::MarginCalculator;
 use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
 use uuid::Uuid;
 use std::collections::HashMap;
-use std::str::FromStr;
 
 impl Account {
     pub fn new(user_id: Uuid) -> Self {
         Account {
             user_id,
-            balances: HashMap::new(),
+            balances: Balances::default(),
             positions: HashMap::new(),
+            margin_types: HashMap::new(),
+            order_holds: HashMap::new(),
+            position_mode: PositionMode::OneWay,
         }
     }
 
-    pub fn deposit(&mut self, asset: String, amount: BigDecimal) {
-        *self.balances.entry(asset).or_insert(BigDecimal::from(0)) += amount;
+    /**
+     * key of a position in positions: the symbol, with the leg appended in hedge mode
+     */
+    pub fn position_key(symbol: &str, position_side: Option<PositionSide>) -> String {
+        match position_side {
+            None => symbol.to_string(),
+            Some(PositionSide::Long) => format!("{}:LONG", symbol),
+            Some(PositionSide::Short) => format!("{}:SHORT", symbol),
+        }
+    }
+
+    /**
+     * the one-way position on symbol, or one hedge mode leg of it
+     */
+    pub fn position(&self, symbol: &str, position_side: Option<PositionSide>) -> Option<&Position> {
+        self.positions.get(&Self::position_key(symbol, position_side))
+    }
+
+    pub fn position_mut(&mut self, symbol: &str, position_side: Option<PositionSide>) -> Option<&mut Position> {
+        self.positions.get_mut(&Self::position_key(symbol, position_side))
+    }
+
+    /**
+     * every open position on symbol, both legs in hedge mode
+     */
+    pub fn open_positions_on<'a>(&'a self, symbol: &'a str) -> impl Iterator<Item = &'a Position> {
+        self.open_positions().filter(move |position| position.symbol == symbol)
+    }
+
+    /**
+     * how positions on symbol are margined, isolated unless set otherwise
+     */
+    pub fn margin_type(&self, symbol: &str) -> MarginType {
+        self.margin_types.get(symbol).copied().unwrap_or(MarginType::Isolated)
+    }
+
+    /**
+     * margin a position ties up: entry notional over leverage, the full notional without leverage
+     */
+    pub fn position_margin(position: &Position) -> BigDecimal {
+        let leverage = position.leverage.clone().unwrap_or(BigDecimal::from(1));
+        &position.entry_price * &position.quantity / leverage
+    }
+
+    /**
+     * profit or loss of a position if it closed at price
+     */
+    pub fn unrealized_pnl(position: &Position, price: &BigDecimal) -> BigDecimal {
+        match position.side {
+            Side::Buy => (price - &position.entry_price) * &position.quantity,
+            Side::Sell => (&position.entry_price - price) * &position.quantity,
+        }
+    }
+
+    fn cross_positions(&self) -> impl Iterator<Item = &Position> {
+        self.open_positions()
+            .filter(|position| self.margin_type(&position.symbol) == MarginType::Cross)
+    }
+
+    pub fn open_positions(&self) -> impl Iterator<Item = &Position> {
+        self.positions.values().filter(|position| position.quantity > BigDecimal::from(0))
+    }
+
+    /**
+     * unrealized PnL of the open positions that pass filter, each at its mark price
+     * positions without a mark are taken at their entry price
+     */
+    pub fn unrealized_pnl_where<F: Fn(&Position) -> bool>(&self, mark_prices: &HashMap<String, BigDecimal>, filter: F) -> BigDecimal {
+        self.open_positions()
+            .filter(|position| filter(position))
+            .map(|position| {
+                let mark = mark_prices.get(&position.symbol).unwrap_or(&position.entry_price);
+                Self::unrealized_pnl(position, mark)
+            })
+            .sum()
+    }
+
+    /**
+     * unrealized losses of the open positions that pass filter as a positive amount, gains count as nothing
+     */
+    pub fn unrealized_losses_where<F: Fn(&Position) -> bool>(&self, mark_prices: &HashMap<String, BigDecimal>, filter: F) -> BigDecimal {
+        self.open_positions()
+            .filter(|position| filter(position))
+            .map(|position| {
+                let mark = mark_prices.get(&position.symbol).unwrap_or(&position.entry_price);
+                (-Self::unrealized_pnl(position, mark)).max(BigDecimal::from(0))
+            })
+            .sum()
+    }
+
+    /**
+     * realized, unrealized, fees and funding over every position, unrealized at the given marks
+     */
+    pub fn pnl_summary(&self, mark_prices: &HashMap<String, BigDecimal>) -> PnlSummary {
+        let realized_pnl: BigDecimal = self.positions.values().map(|position| position.realized_pnl.clone()).sum();
+        let fees: BigDecimal = self.positions.values().map(|position| position.fees.clone()).sum();
+        let funding: BigDecimal = self.positions.values().map(|position| position.funding.clone()).sum();
+        let unrealized_pnl = self.unrealized_pnl_where(mark_prices, |_| true);
+        PnlSummary {
+            net: &realized_pnl + &unrealized_pnl + &funding - &fees,
+            realized_pnl,
+            unrealized_pnl,
+            fees,
+            funding,
+        }
+    }
+
+    /**
+     * re-prices the unrealized PnL of the positions on symbol
+     */
+    pub fn mark_to_market(&mut self, symbol: &str, mark_price: &BigDecimal) {
+        for position in self.positions.values_mut().filter(|position| position.symbol == symbol) {
+            position.unrealized_pnl = if position.quantity > BigDecimal::from(0) {
+                Self::unrealized_pnl(position, mark_price)
+            } else {
+                BigDecimal::from(0)
+            };
+        }
+    }
+
+    /**
+     * what backs the cross positions: the balance not set aside for isolated positions,
+     * plus the unrealized PnL of every cross position at its mark price
+     * positions without a mark are taken at their entry price
+     */
+    pub fn cross_equity(&self, asset: &str, mark_prices: &HashMap<String, BigDecimal>) -> BigDecimal {
+        let isolated_margin: BigDecimal = self.open_positions()
+            .filter(|position| self.margin_type(&position.symbol) == MarginType::Isolated)
+            .map(Self::position_margin)
+            .sum();
+        let unrealized = self.unrealized_pnl_where(mark_prices, |position| self.margin_type(&position.symbol) == MarginType::Cross);
+        self.get_balance(asset) - isolated_margin + unrealized
+    }
+
+    /**
+     * maintenance margin of all cross positions at their mark prices
+     */
+    pub fn cross_maintenance_margin(&self, mark_prices: &HashMap<String, BigDecimal>) -> BigDecimal {
+        self.cross_positions()
+            .map(|position| {
+                let mark = mark_prices.get(&position.symbol).unwrap_or(&position.entry_price);
+                MarginCalculator::calculate_maintenance_margin(&(mark * &position.quantity))
+            })
+            .sum()
+    }
+
+    /**
+     * cross equity over cross maintenance margin, the account is liquidated once it drops to 1
+     * None without open cross positions
+     */
+    pub fn cross_margin_ratio(&self, asset: &str, mark_prices: &HashMap<String, BigDecimal>) -> Option<BigDecimal> {
+        let maintenance = self.cross_maintenance_margin(mark_prices);
+        if maintenance <= BigDecimal::from(0) {
+            return None;
+        }
+        Some(self.cross_equity(asset, mark_prices) / maintenance)
+    }
+
+    /**
+     * cross equity not yet tied up as initial margin of open cross positions or held for open cross orders
+     */
+    pub fn cross_available(&self, asset: &str, mark_prices: &HashMap<String, BigDecimal>) -> BigDecimal {
+        let initial_margin: BigDecimal = self.cross_positions().map(Self::position_margin).sum();
+        let held: BigDecimal = self.order_holds.values()
+            .filter(|hold| hold.asset == asset && self.margin_type(&hold.symbol) == MarginType::Cross)
+            .map(|hold| hold.amount.clone())
+            .sum();
+        self.cross_equity(asset, mark_prices) - initial_margin - held
+    }
+
+    /**
+     * sets margin aside for the unfilled quantity of an order, replacing any earlier hold for it
+     * the balance itself is untouched, holds only count against what is available
+     */
+    pub fn hold_margin(&mut self, order_id: Uuid, symbol: String, asset: String, quantity: BigDecimal, amount: BigDecimal) {
+        if quantity <= BigDecimal::from(0) || amount <= BigDecimal::from(0) {
+            self.order_holds.remove(&order_id);
+            return;
+        }
+        self.order_holds.insert(order_id, OrderHold { symbol, asset, quantity, amount });
+    }
+
+    /**
+     * shrinks an order's hold pro rata to the quantity it still has open and returns what was freed
+     * filled quantity is backed by position margin from then on, cancelled quantity by nothing
+     */
+    pub fn release_hold(&mut self, order_id: Uuid, remaining: &BigDecimal) -> BigDecimal {
+        let hold = match self.order_holds.get_mut(&order_id) {
+            Some(hold) => hold,
+            None => return BigDecimal::from(0),
+        };
+        if remaining >= &hold.quantity {
+            return BigDecimal::from(0);
+        }
+        if remaining <= &BigDecimal::from(0) {
+            return self.order_holds.remove(&order_id).unwrap().amount;
+        }
+
+        let kept = &hold.amount * remaining / &hold.quantity;
+        let released = &hold.amount - &kept;
+        hold.quantity = remaining.clone();
+        hold.amount = kept;
+        released
     }
 
-    pub fn withdraw(&mut self, asset: String) -> BigDecimal {
-        self.balances.get(&asset).cloned().unwrap_or(BigDecimal::from(0))
+    /**
+     * margin held for open orders settled in asset
+     */
+    pub fn held_margin(&self, asset: &str) -> BigDecimal {
+        self.order_holds.values()
+            .filter(|hold| hold.asset == asset)
+            .map(|hold| hold.amount.clone())
+            .sum()
     }
 
     pub fn get_balance(&self, asset: &str) -> BigDecimal {
-        self.balances.get(asset).cloned().unwrap_or(BigDecimal::from(0))
+        self.balances.get(asset)
+    }
+
+    /**
+     * profit or loss a fill would lock in by closing part of the open position on symbol,
+     * or on one leg of it in hedge mode
+     * zero when the fill adds to the position or there is none
+     */
+    pub fn realized_pnl(
+        &self,
+        symbol: &str,
+        position_side: Option<PositionSide>,
+        side: Side,
+        quantity: &BigDecimal,
+        price: &BigDecimal,
+    ) -> BigDecimal {
+        let position = match self.position(symbol, position_side) {
+            Some(position) if position.side != side => position,
+            _ => return BigDecimal::from(0),
+        };
+        let closed = quantity.clone().min(position.quantity.clone());
+        match position.side {
+            Side::Buy => (price - &position.entry_price) * closed,
+            Side::Sell => (&position.entry_price - price) * closed,
+        }
     }
 
+    /**
+     * applies a fill to the position on symbol, or to the position_side leg in hedge mode
+     */
+    #[allow(clippy::too_many_arguments)]
     pub fn update_position(
         &mut self,
         symbol: String,
+        position_side: Option<PositionSide>,
         side: Side,
         quantity: &BigDecimal,
         entry_price: &BigDecimal,
         position_type: PositionType,
         leverage: &Option<BigDecimal>,
         margin_type: &Option<MarginType>,
+        reduce_only: bool,
+        now: DateTime<Utc>,
     ) -> Result<(), OrderError> {
-        let original_position = self.positions.get(&symbol);
+        let key = Self::position_key(&symbol, position_side);
+        let original_position = self.positions.get(&key);
         let original_side = original_position.map(|p| p.side);
         let original_quantity = original_position.map(|p| p.quantity.clone()).unwrap_or(BigDecimal::from(0));
         let original_entry_price = original_position.map(|p| p.entry_price.clone()).unwrap_or(BigDecimal::from(0));
 
-        let new_quantity = if original_side.map_or(true, |s| s == side) {
+        // reduce-only fills can close the position but never grow or flip it, the book holds them to
+        // that while matching so getting here with one that would means the fill was never capped
+        let reducing = original_side.is_some_and(|s| s != side);
+        if reduce_only && (!reducing || quantity > &original_quantity) {
+            return Err(OrderError::ReduceOnlyWouldIncrease);
+        }
+
+        let new_quantity = if original_side.is_none_or(|s| s == side) {
             original_quantity.clone() + quantity.clone()
         } else {
             if quantity > &original_quantity {
@@ -51,7 +303,7 @@ impl Account {
             }
         };
 
-        let new_entry_price = if original_side.map_or(true, |s| s == side) {
+        let new_entry_price = if original_side.is_none_or(|s| s == side) {
             let total_value = original_quantity.clone() * original_entry_price.clone() 
                 + quantity.clone() * entry_price.clone();
             total_value / (original_quantity.clone() + quantity.clone())
@@ -63,7 +315,7 @@ impl Account {
             }
         };
 
-        let position = self.positions.entry(symbol.clone()).or_insert(Position { 
+        let position = self.positions.entry(key).or_insert(Position { 
             user_id: self.user_id,
             symbol,
             side,
@@ -73,15 +325,25 @@ impl Account {
             leverage: leverage.clone(),
             liquidation_price: None,
             margin: None,
-            margin_type: margin_type.clone(),
-            updated_at: chrono::Utc::now(),
+            margin_type: *margin_type,
+            position_side,
+            realized_pnl: BigDecimal::from(0),
+            unrealized_pnl: BigDecimal::from(0),
+            fees: BigDecimal::from(0),
+            funding: BigDecimal::from(0),
+            updated_at: now,
         });
 
+        // a fill past what is open flips the position, one onto a flat position reopens it, either way
+        // what is left is on the fill's side
+        if quantity > &original_quantity {
+            position.side = side;
+        }
         position.quantity = new_quantity;
         position.entry_price = new_entry_price;
-        position.updated_at = chrono::Utc::now();
+        position.updated_at = now;
 
-        /**
+        /*
          * update margin-related fields if it's a margin position
          */
         if position_type == PositionType::Margin {
@@ -89,13 +351,16 @@ impl Account {
                 position.leverage = Some(leverage.clone());
                 position.margin_type = Some(*margin_type);
                 
-                if position.quantity > BigDecimal::from(0) {
+                // cross positions are liquidated on the account's margin ratio, not a price of their own
+                if position.quantity > BigDecimal::from(0) && *margin_type == MarginType::Isolated {
                     position.liquidation_price = Some(MarginCalculator::calculate_liquidation_price(
                         &position.entry_price,
                         position.side,
                         leverage,
                         *margin_type,
                     ));
+                } else {
+                    position.liquidation_price = None;
                 }
             }
         }
@@ -114,35 +379,13 @@ impl Account {
             return Ok(());
         }
 
+        // the balance to cover the order is checked by the exchange, in the symbol's settlement asset
         let leverage = order.leverage.as_ref().unwrap();
         let margin_type = margin_type.unwrap();
-        
-        let required_margin = MarginCalculator::calculate_required_margin(
-            &order.quantity,
-            &order.price,
-            leverage,
-            margin_type,
-        );
-
-        // Check if account has enough balance
-        let balance = self.get_balance("USDT"); // Assuming USDT margined
-        if balance < required_margin {
-            return Err(OrderError::InsufficientBalance);
-        }
 
         // Check if position would be liquidated
-        if let Some(position) = self.positions.get(&order.symbol) {
+        if let Some(position) = self.position(&order.symbol, order.position_side) {
             if position.position_type == PositionType::Margin {
-                let new_quantity = if position.side == order.side {
-                    position.quantity.clone() + order.quantity.clone()
-                } else {
-                    if order.quantity > position.quantity {
-                        order.quantity.clone() - position.quantity.clone()
-                    } else {
-                        position.quantity.clone() - order.quantity.clone()
-                    }
-                };
-
                 let new_entry_price = if order.side == position.side {
                     (position.quantity.clone() * position.entry_price.clone() 
                         + order.quantity.clone() * order.price.clone()) 
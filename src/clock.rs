@@ -0,0 +1,59 @@
+use chrono::{DateTime, Duration, Utc};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+/**
+ * source of the current time for everything that stamps or compares timestamps
+ */
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/**
+ * wall clock time
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/**
+ * clock that only moves when told to, for deterministic tests and replays
+ */
+#[derive(Debug)]
+pub struct SimulatedClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+impl SimulatedClock {
+    pub fn new(start: DateTime<Utc>) -> Self {
+        SimulatedClock {
+            now: Mutex::new(start),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+
+    pub fn set(&self, to: DateTime<Utc>) {
+        *self.now.lock().unwrap() = to;
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
+/**
+ * the clock components fall back to when none is injected
+ */
+pub fn system_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
@@ -0,0 +1,80 @@
+use crate::models::{Order, OrderError, OrderType};
+use bigdecimal::BigDecimal;
+use serde::{Deserialize, Serialize};
+
+/**
+ * trading rules for a single symbol
+ * tick_size: prices must be a multiple of this
+ * lot_size: quantities must be a multiple of this
+ * min_notional: smallest quantity * price accepted
+ * price_precision: most decimal places a limit or trigger price may have
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContractSpec {
+    pub symbol: String,
+    pub base_asset: String,
+    pub quote_asset: String,
+    pub tick_size: BigDecimal,
+    pub lot_size: BigDecimal,
+    pub min_quantity: BigDecimal,
+    pub max_quantity: BigDecimal,
+    pub min_notional: BigDecimal,
+    pub max_leverage: BigDecimal,
+    pub price_precision: u32,
+}
+
+impl ContractSpec {
+    /**
+     * checks an order against the contract rules
+     * market orders have no limit price, so their notional is taken at the reference (mark) price
+     */
+    pub fn validate_order(&self, order: &Order, reference_price: &BigDecimal) -> Result<(), OrderError> {
+        let zero = BigDecimal::from(0);
+
+        let has_limit_price = matches!(order.order_type, OrderType::Limit | OrderType::StopLimit);
+        let prices = has_limit_price.then_some(&order.price).into_iter().chain(&order.trigger_price);
+        for price in prices {
+            if price <= &zero || !Self::is_multiple(price, &self.tick_size) {
+                return Err(OrderError::PriceNotOnTick);
+            }
+            if price.with_scale(self.price_precision as i64) != *price {
+                return Err(OrderError::PriceTooPrecise);
+            }
+        }
+
+        if !Self::is_multiple(&order.quantity, &self.lot_size) {
+            return Err(OrderError::QuantityNotOnLot);
+        }
+        if let Some(display) = &order.display_quantity {
+            if !Self::is_multiple(display, &self.lot_size) {
+                return Err(OrderError::QuantityNotOnLot);
+            }
+        }
+        if order.quantity < self.min_quantity || order.quantity <= zero {
+            return Err(OrderError::QuantityBelowMinimum);
+        }
+        if order.quantity > self.max_quantity {
+            return Err(OrderError::QuantityAboveMaximum);
+        }
+
+        let notional_price = if has_limit_price { &order.price } else { reference_price };
+        if order.quantity.clone() * notional_price.clone() < self.min_notional {
+            return Err(OrderError::NotionalBelowMinimum);
+        }
+
+        if let Some(leverage) = &order.leverage {
+            if leverage <= &zero {
+                return Err(OrderError::InvalidOrder);
+            }
+            if leverage > &self.max_leverage {
+                return Err(OrderError::LeverageAboveMaximum);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_multiple(value: &BigDecimal, step: &BigDecimal) -> bool {
+        step <= &BigDecimal::from(0) || (value % step) == BigDecimal::from(0)
+    }
+}
@@ -1,10 +1,25 @@
-use crate::models::{Order, Trade, OrderError, FundingRate, Side, PositionType, MarginType, OrderBook, Account};
+use crate::models::{
+    Order, Trade, MatchResult, OrderError, FundingRate, Side, PositionType, MarginType, OrderBook, Account,
+    LiquidationEvent, AdlEvent, OrderType, TimeInForce, TriggerBy, PnlSummary, Position, PositionMode, PositionSide,
+    ReduceOnlyLimits,
+};
+use crate::clock::{system_clock, Clock, SimulatedClock};
+use crate::contract::ContractSpec;
+use crate::fees::{FeeEngine, FeeSchedule};
+use crate::insurance::{InsuranceFund, InsuranceFundEntry, InsuranceFundReason};
+use crate::ledger::{Ledger, LedgerAccount, LedgerReason, LedgerReference};
+use crate::withdrawal::{Withdrawal, WithdrawalStatus};
 use crate::funding::FundingCalculator;
+use crate::journal::{Command, Journal, JournalEntry};
 use crate::margin::MarginCalculator;
+use crate::snapshot::{self, SnapshotFormat};
 use bigdecimal::BigDecimal;
 use uuid::Uuid;
 use std::collections::HashMap;
 use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
 
 /**
  * exchange module implementation
@@ -12,7 +27,7 @@ use chrono::{Duration, Utc};
  * supports margin trading with configurable quote assets
  */
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MarketData {
     pub symbol: String,
     pub mark_price: BigDecimal,
@@ -20,51 +35,585 @@ pub struct MarketData {
     pub open_interest_long: BigDecimal,
     pub open_interest_short: BigDecimal,
     pub last_update: chrono::DateTime<Utc>,
+    pub sequence: u64, // updates applied to this symbol, so feeds can spot a missed one
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Exchange {
     pub accounts: HashMap<Uuid, Account>,
     pub order_books: HashMap<String, OrderBook>,
     pub funding_calculator: FundingCalculator,
     pub symbols: Vec<String>,
+    pub contract_specs: HashMap<String, ContractSpec>,
     pub market_data: HashMap<String, MarketData>,
     pub last_trade_prices: HashMap<String, BigDecimal>,
     pub quote_asset: String,
+    pub reduce_only_orders: HashMap<Uuid, (Uuid, String)>, // order id -> (user, symbol)
+    pub fee_engine: FeeEngine,
+    pub insurance_funds: HashMap<String, InsuranceFund>, // quote asset -> fund
+    pub withdrawals: HashMap<Uuid, Withdrawal>,
+    pub ledger: Ledger,
+    #[serde(skip, default = "system_clock")]
+    pub clock: Arc<dyn Clock>,
+    #[serde(skip)]
+    pub journal: Option<Journal>,
+    pub journal_sequence: u64, // last journal entry reflected in this state
 }
 
 impl Exchange {
-    pub fn new(symbols: Vec<String>, funding_interval: Duration, quote_asset: String) -> Self {
+    pub fn new(specs: Vec<ContractSpec>, funding_interval: Duration, quote_asset: String) -> Self {
+        Self::with_clock(specs, funding_interval, quote_asset, system_clock())
+    }
+
+    /**
+     * exchange whose books, funding and staleness checks all read time from the given clock
+     */
+    pub fn with_clock(
+        specs: Vec<ContractSpec>,
+        funding_interval: Duration,
+        quote_asset: String,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
         let mut order_books = HashMap::new();
         let mut market_data = HashMap::new();
         let mut last_trade_prices = HashMap::new();
+        let mut contract_specs = HashMap::new();
+        let symbols: Vec<String> = specs.iter().map(|spec| spec.symbol.clone()).collect();
 
-        for symbol in &symbols {
-            order_books.insert(symbol.clone(), OrderBook::new(symbol.clone()));
+        for spec in specs {
+            let symbol = &spec.symbol;
+            let mut order_book = OrderBook::with_clock(symbol.clone(), clock.clone());
+            order_book.tick_size = spec.tick_size.clone();
+            order_books.insert(symbol.clone(), order_book);
             market_data.insert(symbol.clone(), MarketData {
                 symbol: symbol.clone(),
                 mark_price: BigDecimal::from(0),
                 index_price: BigDecimal::from(0),
                 open_interest_long: BigDecimal::from(0),
                 open_interest_short: BigDecimal::from(0),
-                last_update: Utc::now(),
+                last_update: clock.now(),
+                sequence: 0,
             });
             last_trade_prices.insert(symbol.clone(), BigDecimal::from(0));
+            contract_specs.insert(symbol.clone(), spec);
         }
 
         Exchange {
             accounts: HashMap::new(),
             order_books,
-            funding_calculator: FundingCalculator::new(funding_interval),
+            funding_calculator: FundingCalculator::with_clock(funding_interval, clock.clone()),
             symbols,
+            contract_specs,
             market_data,
             last_trade_prices,
             quote_asset,
+            reduce_only_orders: HashMap::new(),
+            fee_engine: FeeEngine::new(),
+            insurance_funds: HashMap::new(),
+            withdrawals: HashMap::new(),
+            ledger: Ledger::new(),
+            clock,
+            journal: None,
+            journal_sequence: 0,
+        }
+    }
+
+    /**
+     * new exchange that journals every state-changing call to an empty journal
+     * the journal starts with a Genesis entry holding this configuration
+     */
+    pub fn with_journal(
+        specs: Vec<ContractSpec>,
+        funding_interval: Duration,
+        quote_asset: String,
+        clock: Arc<dyn Clock>,
+        journal: Journal,
+    ) -> Result<Self, OrderError> {
+        if journal.last_sequence() != 0 {
+            return Err(OrderError::JournalError("journal already has entries, replay it instead".to_string()));
+        }
+
+        let genesis = Command::Genesis {
+            specs: specs.clone(),
+            funding_interval_ms: funding_interval.num_milliseconds(),
+            quote_asset: quote_asset.clone(),
+        };
+        let mut exchange = Self::with_clock(specs, funding_interval, quote_asset, clock);
+        exchange.journal = Some(journal);
+        exchange.record(|| genesis)?;
+        Ok(exchange)
+    }
+
+    /**
+     * rebuilds an exchange from the journal at path
+     * every command runs again at the time it was recorded, then the exchange is handed the given clock
+     * no journal is attached to the result, use attach_journal to keep appending to the same file
+     */
+    pub fn replay<P: AsRef<Path>>(path: P, clock: Arc<dyn Clock>) -> Result<Self, OrderError> {
+        let entries = Journal::read(path)
+            .map_err(|e| OrderError::JournalError(e.to_string()))?;
+        let (genesis, rest) = entries.split_first()
+            .ok_or(OrderError::JournalError("journal is empty".to_string()))?;
+        let (specs, funding_interval_ms, quote_asset) = match &genesis.command {
+            Command::Genesis { specs, funding_interval_ms, quote_asset } => (specs, funding_interval_ms, quote_asset),
+            _ => return Err(OrderError::JournalError("journal does not start with Genesis".to_string())),
+        };
+
+        let mut exchange = Self::with_clock(
+            specs.clone(),
+            Duration::milliseconds(*funding_interval_ms),
+            quote_asset.clone(),
+            Arc::new(SimulatedClock::new(genesis.recorded_at)),
+        );
+        exchange.journal_sequence = genesis.sequence;
+        exchange.replay_entries(rest);
+        exchange.set_clock(clock);
+        Ok(exchange)
+    }
+
+    /**
+     * runs journaled commands against this exchange, each at its recorded time
+     * results are dropped: a command that was rejected live is rejected again here
+     * nothing is journaled while replaying
+     */
+    pub fn replay_entries(&mut self, entries: &[JournalEntry]) {
+        let journal = self.journal.take();
+        let live_clock = self.clock.clone();
+        let replay_clock = Arc::new(SimulatedClock::new(live_clock.now()));
+        self.set_clock(replay_clock.clone());
+
+        for entry in entries {
+            replay_clock.set(entry.recorded_at);
+            let _ = self.apply(entry.command.clone());
+            self.journal_sequence = entry.sequence;
+        }
+
+        self.set_clock(live_clock);
+        self.journal = journal;
+    }
+
+    fn apply(&mut self, command: Command) -> Result<(), OrderError> {
+        match command {
+            Command::Genesis { .. } => {}
+            Command::CreateAccount { user_id } => {
+                self.create_account(user_id)?;
+            }
+            Command::Deposit { user_id, asset, amount } => self.deposit(user_id, asset, amount)?,
+            Command::PlaceOrder { order } => {
+                self.place_order(order)?;
+            }
+            Command::CancelOrder { user_id, symbol, order_id } => {
+                self.cancel_order(user_id, symbol, order_id)?;
+            }
+            Command::AmendOrder { user_id, symbol, order_id, price, quantity } => {
+                self.amend_order(user_id, symbol, order_id, price, quantity)?;
+            }
+            Command::UpdateMarketData { symbol, mark_price, index_price, open_interest_long, open_interest_short } => {
+                self.update_market_data(&symbol, mark_price, index_price, open_interest_long, open_interest_short)?;
+            }
+            Command::RunFunding => {
+                self.run_funding()?;
+            }
+            Command::ExpireOrders => {
+                self.expire_orders()?;
+            }
+            Command::SetFeeSchedule { symbol, schedule } => self.set_fee_schedule(symbol, schedule)?,
+            Command::SeedInsuranceFund { asset, amount } => self.seed_insurance_fund(asset, amount)?,
+            Command::SetMarginType { user_id, symbol, margin_type } => self.set_margin_type(user_id, symbol, margin_type)?,
+            Command::SetPositionMode { user_id, position_mode } => self.set_position_mode(user_id, position_mode)?,
+            Command::RequestWithdrawal { withdrawal_id, user_id, asset, amount } => {
+                self.open_withdrawal(withdrawal_id, user_id, asset, amount)?;
+            }
+            Command::ApproveWithdrawal { withdrawal_id } => self.approve_withdrawal(withdrawal_id)?,
+            Command::CompleteWithdrawal { withdrawal_id } => self.complete_withdrawal(withdrawal_id)?,
+            Command::RejectWithdrawal { withdrawal_id, reason } => self.reject_withdrawal(withdrawal_id, reason)?,
+            Command::Transfer { from_user_id, to_user_id, asset, amount } => self.transfer(from_user_id, to_user_id, asset, amount)?,
+        }
+        Ok(())
+    }
+
+    /**
+     * captures the whole engine state, including funding history and the journal position it reflects
+     */
+    pub fn snapshot(&self, format: SnapshotFormat) -> Result<Vec<u8>, OrderError> {
+        snapshot::encode(self, self.clock.now(), format)
+    }
+
+    /**
+     * rebuilds an exchange from a snapshot, running on the given clock
+     * no journal is attached, see recover to catch up with one
+     */
+    pub fn restore(bytes: &[u8], format: SnapshotFormat, clock: Arc<dyn Clock>) -> Result<Self, OrderError> {
+        let mut exchange = snapshot::decode::<Exchange>(bytes, format)?.state;
+        exchange.set_clock(clock);
+        Ok(exchange)
+    }
+
+    /**
+     * restores a snapshot, then replays the journal entries written after it was taken
+     * ends up where the exchange would be had it never stopped
+     */
+    pub fn recover<P: AsRef<Path>>(
+        bytes: &[u8],
+        format: SnapshotFormat,
+        journal_path: P,
+        clock: Arc<dyn Clock>,
+    ) -> Result<Self, OrderError> {
+        let mut exchange = Self::restore(bytes, format, clock)?;
+        let entries = Journal::read(journal_path)
+            .map_err(|e| OrderError::JournalError(e.to_string()))?;
+        let tail: Vec<JournalEntry> = entries.into_iter()
+            .filter(|entry| entry.sequence > exchange.journal_sequence)
+            .collect();
+        exchange.replay_entries(&tail);
+        Ok(exchange)
+    }
+
+    pub fn attach_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    /**
+     * swaps the time source for the exchange and everything under it
+     */
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        for order_book in self.order_books.values_mut() {
+            order_book.clock = clock.clone();
+        }
+        self.funding_calculator.set_clock(clock.clone());
+        self.clock = clock;
+    }
+
+    /**
+     * appends a command to the journal ahead of running it, if one is attached
+     * the command is only built when it will actually be written
+     */
+    fn record<F: FnOnce() -> Command>(&mut self, command: F) -> Result<(), OrderError> {
+        if let Some(journal) = &mut self.journal {
+            self.journal_sequence = journal.append(self.clock.now(), command())
+                .map_err(|e| OrderError::JournalError(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    pub fn create_account(&mut self, user_id: Uuid) -> Result<&mut Account, OrderError> {
+        self.record(|| Command::CreateAccount { user_id })?;
+        Ok(self.accounts.entry(user_id)
+            .or_insert_with(|| Account::new(user_id)))
+    }
+
+    /**
+     * maker/taker tiers for a symbol, trades from now on are charged by it
+     */
+    pub fn set_fee_schedule(&mut self, symbol: String, schedule: FeeSchedule) -> Result<(), OrderError> {
+        self.record(|| Command::SetFeeSchedule { symbol: symbol.clone(), schedule: schedule.clone() })?;
+        if !self.contract_specs.contains_key(&symbol) {
+            return Err(OrderError::UnknownSymbol);
+        }
+        self.fee_engine.set_schedule(symbol, schedule);
+        Ok(())
+    }
+
+    /**
+     * adds money to the insurance fund for a quote asset
+     */
+    pub fn seed_insurance_fund(&mut self, asset: String, amount: BigDecimal) -> Result<(), OrderError> {
+        self.record(|| Command::SeedInsuranceFund { asset: asset.clone(), amount: amount.clone() })?;
+        if amount <= BigDecimal::from(0) {
+            return Err(OrderError::InvalidOrder);
+        }
+        let now = self.clock.now();
+        self.insurance_funds.entry(asset.clone())
+            .or_default()
+            .credit(InsuranceFundReason::Seed, amount.clone(), None, None, now);
+        self.post(LedgerAccount::External, LedgerAccount::InsuranceFund, &asset, amount, LedgerReason::InsuranceSeed, None)
+    }
+
+    pub fn insurance_fund_balance(&self, asset: &str) -> BigDecimal {
+        self.insurance_funds.get(asset)
+            .map(|fund| fund.balance().clone())
+            .unwrap_or(BigDecimal::from(0))
+    }
+
+    /**
+     * every movement of an asset's insurance fund, oldest first
+     */
+    pub fn insurance_fund_history(&self, asset: &str) -> &[InsuranceFundEntry] {
+        self.insurance_funds.get(asset)
+            .map(|fund| fund.history())
+            .unwrap_or(&[])
+    }
+
+    /**
+     * switches how a user's positions on symbol are margined
+     * only allowed while the user has neither a position nor open orders there
+     */
+    pub fn set_margin_type(&mut self, user_id: Uuid, symbol: String, margin_type: MarginType) -> Result<(), OrderError> {
+        self.record(|| Command::SetMarginType { user_id, symbol: symbol.clone(), margin_type })?;
+        let order_book = self.order_books.get(&symbol)
+            .ok_or(OrderError::UnknownSymbol)?;
+        let has_orders = order_book.orders.values()
+            .chain(order_book.stop_orders.values())
+            .any(|order| order.user_id == user_id);
+
+        let account = self.get_account(user_id)?;
+        let has_position = account.open_positions_on(&symbol).next().is_some();
+        if has_orders || has_position {
+            return Err(OrderError::MarginTypeLocked);
+        }
+        account.margin_types.insert(symbol, margin_type);
+        Ok(())
+    }
+
+    /**
+     * switches a user between one netted position per symbol and hedge mode long and short legs
+     * only allowed while the user has no open position or order on any symbol
+     */
+    pub fn set_position_mode(&mut self, user_id: Uuid, position_mode: PositionMode) -> Result<(), OrderError> {
+        self.record(|| Command::SetPositionMode { user_id, position_mode })?;
+        let has_orders = self.order_books.values()
+            .flat_map(|order_book| order_book.orders.values().chain(order_book.stop_orders.values()))
+            .any(|order| order.user_id == user_id);
+
+        let account = self.get_account(user_id)?;
+        if has_orders || account.open_positions().next().is_some() {
+            return Err(OrderError::PositionModeLocked);
+        }
+        account.position_mode = position_mode;
+        Ok(())
+    }
+
+    /**
+     * latest mark price of every symbol with market data
+     */
+    pub fn mark_prices(&self) -> HashMap<String, BigDecimal> {
+        self.market_data.iter()
+            .map(|(symbol, market_data)| (symbol.clone(), market_data.mark_price.clone()))
+            .collect()
+    }
+
+    /**
+     * asset a symbol's fills, fees and liquidations settle in
+     */
+    fn settlement_asset(&self, symbol: &str) -> String {
+        self.contract_specs.get(symbol)
+            .map(|spec| spec.quote_asset.clone())
+            .unwrap_or(self.quote_asset.clone())
+    }
+
+    pub fn deposit(&mut self, user_id: Uuid, asset: String, amount: BigDecimal) -> Result<(), OrderError> {
+        self.record(|| Command::Deposit { user_id, asset: asset.clone(), amount: amount.clone() })?;
+        if amount <= BigDecimal::from(0) {
+            return Err(OrderError::InvalidOrder);
+        }
+        self.get_account(user_id)?;
+        self.post(LedgerAccount::External, LedgerAccount::User(user_id), &asset, amount, LedgerReason::Deposit, None)
+    }
+
+    /**
+     * moves funds between two users, limited to what the sender has available
+     */
+    pub fn transfer(&mut self, from_user_id: Uuid, to_user_id: Uuid, asset: String, amount: BigDecimal) -> Result<(), OrderError> {
+        self.record(|| Command::Transfer { from_user_id, to_user_id, asset: asset.clone(), amount: amount.clone() })?;
+        if amount <= BigDecimal::from(0) || from_user_id == to_user_id {
+            return Err(OrderError::InvalidOrder);
+        }
+        self.get_account(to_user_id)?;
+        if self.available_balance(from_user_id, &asset)? < amount {
+            return Err(OrderError::InsufficientBalance);
         }
+        self.post(LedgerAccount::User(from_user_id), LedgerAccount::User(to_user_id), &asset, amount, LedgerReason::Transfer, None)
     }
 
-    pub fn create_account(&mut self, user_id: Uuid) -> &mut Account {
-        self.accounts.entry(user_id)
-            .or_insert_with(|| Account::new(user_id))
+    /**
+     * records a movement in the ledger at the exchange's time, the ledger moves the users' balances with it
+     * a negative amount moves money from credit to debit, a zero amount does nothing
+     */
+    fn post(
+        &mut self,
+        debit: LedgerAccount,
+        credit: LedgerAccount,
+        asset: &str,
+        amount: BigDecimal,
+        reason: LedgerReason,
+        reference: Option<LedgerReference>,
+    ) -> Result<(), OrderError> {
+        let now = self.clock.now();
+        self.ledger.post(&mut self.accounts, debit, credit, asset.to_string(), amount, reason, reference, now)
+    }
+
+    /**
+     * sum of every ledger balance per asset, all zero while the books balance
+     */
+    pub fn trial_balance(&self) -> HashMap<String, BigDecimal> {
+        self.ledger.trial_balance()
+    }
+
+    /**
+     * checks the books: the trial balance nets to zero, every account balance and insurance fund
+     * matches the ledger, and pending withdrawals are exactly what sits in the ledger for them
+     */
+    pub fn verify_ledger(&self) -> Result<(), OrderError> {
+        let zero = BigDecimal::from(0);
+        if let Some((asset, total)) = self.ledger.trial_balance().into_iter().find(|(_, total)| total != &zero) {
+            return Err(OrderError::LedgerMismatch(format!("trial balance of {} is {}", asset, total)));
+        }
+
+        for (user_id, account) in &self.accounts {
+            for (asset, balance) in account.balances.iter() {
+                let booked = self.ledger.balance(LedgerAccount::User(*user_id), asset);
+                if &booked != balance {
+                    return Err(OrderError::LedgerMismatch(format!("{} {} balance is {}, ledger has {}", user_id, asset, balance, booked)));
+                }
+            }
+        }
+        for (asset, fund) in &self.insurance_funds {
+            let booked = self.ledger.balance(LedgerAccount::InsuranceFund, asset);
+            if &booked != fund.balance() {
+                return Err(OrderError::LedgerMismatch(format!("{} insurance fund is {}, ledger has {}", asset, fund.balance(), booked)));
+            }
+        }
+
+        let mut pending: HashMap<&str, BigDecimal> = HashMap::new();
+        for withdrawal in self.withdrawals.values() {
+            if matches!(withdrawal.status, WithdrawalStatus::Pending | WithdrawalStatus::Approved) {
+                *pending.entry(&withdrawal.asset).or_insert(BigDecimal::from(0)) += &withdrawal.amount;
+            }
+        }
+        for (asset, amount) in pending {
+            let booked = self.ledger.balance(LedgerAccount::PendingWithdrawals, asset);
+            if booked != amount {
+                return Err(OrderError::LedgerMismatch(format!("{} pending withdrawals are {}, ledger has {}", asset, amount, booked)));
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * what a user could take out of asset right now: the balance less margin held by open
+     * positions, margin for open orders and unrealized losses at the mark price
+     */
+    pub fn available_balance(&self, user_id: Uuid, asset: &str) -> Result<BigDecimal, OrderError> {
+        let account = self.accounts.get(&user_id)
+            .ok_or(OrderError::OrderNotFound)?;
+        let mark_prices = self.mark_prices();
+
+        let settles_in_asset = |position: &Position| self.settlement_asset(&position.symbol) == asset;
+        let position_margin: BigDecimal = account.open_positions()
+            .filter(|position| settles_in_asset(position))
+            .map(Account::position_margin)
+            .sum();
+        let in_use = position_margin
+            + account.unrealized_losses_where(&mark_prices, settles_in_asset)
+            + account.held_margin(asset);
+
+        Ok((account.get_balance(asset) - in_use).max(BigDecimal::from(0)))
+    }
+
+    /**
+     * opens a withdrawal and takes the amount off the balance straight away, so it cannot be
+     * traded or withdrawn twice while it waits for approval
+     */
+    pub fn request_withdrawal(&mut self, user_id: Uuid, asset: String, amount: BigDecimal) -> Result<Uuid, OrderError> {
+        let withdrawal_id = Uuid::new_v4();
+        self.record(|| Command::RequestWithdrawal {
+            withdrawal_id,
+            user_id,
+            asset: asset.clone(),
+            amount: amount.clone(),
+        })?;
+        self.open_withdrawal(withdrawal_id, user_id, asset, amount)
+    }
+
+    fn open_withdrawal(&mut self, withdrawal_id: Uuid, user_id: Uuid, asset: String, amount: BigDecimal) -> Result<Uuid, OrderError> {
+        if amount <= BigDecimal::from(0) {
+            return Err(OrderError::InvalidOrder);
+        }
+        if self.available_balance(user_id, &asset)? < amount {
+            return Err(OrderError::InsufficientBalance);
+        }
+
+        self.post(
+            LedgerAccount::User(user_id),
+            LedgerAccount::PendingWithdrawals,
+            &asset,
+            amount.clone(),
+            LedgerReason::Withdrawal,
+            Some(LedgerReference::Withdrawal(withdrawal_id)),
+        )?;
+        let withdrawal = Withdrawal::new(withdrawal_id, user_id, asset, amount, self.clock.now());
+        self.withdrawals.insert(withdrawal_id, withdrawal);
+        Ok(withdrawal_id)
+    }
+
+    pub fn approve_withdrawal(&mut self, withdrawal_id: Uuid) -> Result<(), OrderError> {
+        self.record(|| Command::ApproveWithdrawal { withdrawal_id })?;
+        self.move_withdrawal(withdrawal_id, WithdrawalStatus::Approved, None)?;
+        Ok(())
+    }
+
+    /**
+     * marks an approved withdrawal as paid out
+     */
+    pub fn complete_withdrawal(&mut self, withdrawal_id: Uuid) -> Result<(), OrderError> {
+        self.record(|| Command::CompleteWithdrawal { withdrawal_id })?;
+        let withdrawal = self.move_withdrawal(withdrawal_id, WithdrawalStatus::Completed, None)?;
+        self.post(
+            LedgerAccount::PendingWithdrawals,
+            LedgerAccount::External,
+            &withdrawal.asset,
+            withdrawal.amount,
+            LedgerReason::WithdrawalPaid,
+            Some(LedgerReference::Withdrawal(withdrawal_id)),
+        )
+    }
+
+    /**
+     * turns a pending or approved withdrawal down and puts the amount back on the balance
+     */
+    pub fn reject_withdrawal(&mut self, withdrawal_id: Uuid, reason: String) -> Result<(), OrderError> {
+        self.record(|| Command::RejectWithdrawal { withdrawal_id, reason: reason.clone() })?;
+        let withdrawal = self.move_withdrawal(withdrawal_id, WithdrawalStatus::Rejected, Some(reason))?;
+        self.post(
+            LedgerAccount::PendingWithdrawals,
+            LedgerAccount::User(withdrawal.user_id),
+            &withdrawal.asset,
+            withdrawal.amount,
+            LedgerReason::WithdrawalRejected,
+            Some(LedgerReference::Withdrawal(withdrawal_id)),
+        )
+    }
+
+    fn move_withdrawal(&mut self, withdrawal_id: Uuid, status: WithdrawalStatus, note: Option<String>) -> Result<Withdrawal, OrderError> {
+        let now = self.clock.now();
+        let withdrawal = self.withdrawals.get_mut(&withdrawal_id)
+            .ok_or(OrderError::WithdrawalNotFound)?;
+        withdrawal.transition(status, now, note)?;
+        Ok(withdrawal.clone())
+    }
+
+    pub fn get_withdrawal(&self, withdrawal_id: Uuid) -> Option<&Withdrawal> {
+        self.withdrawals.get(&withdrawal_id)
+    }
+
+    /**
+     * a user's withdrawals, oldest request first
+     */
+    pub fn get_withdrawals(&self, user_id: Uuid) -> Vec<&Withdrawal> {
+        let mut withdrawals: Vec<&Withdrawal> = self.withdrawals.values()
+            .filter(|withdrawal| withdrawal.user_id == user_id)
+            .collect();
+        withdrawals.sort_by_key(|withdrawal| (withdrawal.history[0].at, withdrawal.id));
+        withdrawals
+    }
+
+    /**
+     * a user's PnL over all positions, unrealized at the current mark prices
+     */
+    pub fn account_pnl(&self, user_id: Uuid) -> Result<PnlSummary, OrderError> {
+        let account = self.accounts.get(&user_id)
+            .ok_or(OrderError::OrderNotFound)?;
+        Ok(account.pnl_summary(&self.mark_prices()))
     }
 
     pub fn get_account(&mut self, user_id: Uuid) -> Result<&mut Account, OrderError> {
@@ -79,160 +628,918 @@ impl Exchange {
         index_price: BigDecimal,
         open_interest_long: BigDecimal,
         open_interest_short: BigDecimal,
-    ) {
-        if let Some(market_data) = self.market_data.get_mut(symbol) {
-            market_data.mark_price = mark_price;
-            market_data.index_price = index_price;
-            market_data.open_interest_long = open_interest_long;
-            market_data.open_interest_short = open_interest_short;
-            market_data.last_update = Utc::now();
+    ) -> Result<MatchResult, OrderError> {
+        self.record(|| Command::UpdateMarketData {
+            symbol: symbol.to_string(),
+            mark_price: mark_price.clone(),
+            index_price: index_price.clone(),
+            open_interest_long: open_interest_long.clone(),
+            open_interest_short: open_interest_short.clone(),
+        })?;
+
+        match self.market_data.get_mut(symbol) {
+            Some(market_data) => {
+                market_data.mark_price = mark_price.clone();
+                market_data.index_price = index_price;
+                market_data.open_interest_long = open_interest_long;
+                market_data.open_interest_short = open_interest_short;
+                market_data.last_update = self.clock.now();
+                market_data.sequence += 1;
+            }
+            None => return Ok(MatchResult::default()),
         }
+        for account in self.accounts.values_mut() {
+            account.mark_to_market(symbol, &mark_price);
+        }
+
+        // a new mark price can push positions under water and fire stops watching it
+        let mut result = self.liquidate_positions(symbol)?;
+        result.extend(self.trigger_stop_orders(symbol)?);
+        Ok(result)
     }
 
-    pub fn place_order(&mut self, order: Order) -> Result<Vec<Trade>, OrderError> {
-        if !self.symbols.contains(&order.symbol) {
-            return Err(OrderError::InvalidOrder);
+    /**
+     * closes every isolated position on symbol whose liquidation price the mark price has crossed,
+     * then every cross account holding symbol whose margin ratio has dropped to 1
+     * accounts are visited in user id order so a replay liquidates in the same order
+     */
+    fn liquidate_positions(&mut self, symbol: &str) -> Result<MatchResult, OrderError> {
+        let mark_price = match self.market_data.get(symbol) {
+            Some(market_data) => market_data.mark_price.clone(),
+            None => return Ok(MatchResult::default()),
+        };
+
+        let mut underwater: Vec<(Uuid, Option<PositionSide>)> = self.accounts.values()
+            .flat_map(|account| account.open_positions_on(symbol))
+            .filter(|position| match (&position.liquidation_price, position.side) {
+                (Some(liquidation_price), Side::Buy) => &mark_price <= liquidation_price,
+                (Some(liquidation_price), Side::Sell) => &mark_price >= liquidation_price,
+                (None, _) => false,
+            })
+            .map(|position| (position.user_id, position.position_side))
+            .collect();
+        underwater.sort();
+
+        let mut result = MatchResult::default();
+        for (user_id, position_side) in underwater {
+            let position = match self.accounts[&user_id].position(symbol, position_side) {
+                Some(position) => position.clone(),
+                None => continue,
+            };
+            let leverage = position.leverage.clone().unwrap_or(BigDecimal::from(1));
+            let bankruptcy_price = MarginCalculator::calculate_bankruptcy_price(&position.entry_price, position.side, &leverage);
+            result.extend(self.liquidate_position(user_id, symbol, position_side, &mark_price, bankruptcy_price)?);
         }
 
+        let asset = self.settlement_asset(symbol);
+        let mark_prices = self.mark_prices();
+        let mut under_margined: Vec<Uuid> = self.accounts.values()
+            .filter(|account| account.margin_type(symbol) == MarginType::Cross)
+            .filter(|account| account.open_positions_on(symbol).next().is_some())
+            .filter(|account| account.cross_margin_ratio(&asset, &mark_prices)
+                .is_some_and(|ratio| ratio <= BigDecimal::from(1)))
+            .map(|account| account.user_id)
+            .collect();
+        under_margined.sort();
+
+        for user_id in under_margined {
+            result.extend(self.liquidate_cross_account(user_id, &asset, &mark_prices)?);
+        }
+        Ok(result)
+    }
+
+    /**
+     * closes every cross position of an account settled in asset
+     * the remaining cross equity is split over the positions by notional, each position's
+     * bankruptcy price is where it would have lost its share
+     */
+    fn liquidate_cross_account(
+        &mut self,
+        user_id: Uuid,
+        asset: &str,
+        mark_prices: &HashMap<String, BigDecimal>,
+    ) -> Result<MatchResult, OrderError> {
+        let account = &self.accounts[&user_id];
+        let equity = account.cross_equity(asset, mark_prices);
+        let mut positions: Vec<(String, Option<PositionSide>, Side, BigDecimal, BigDecimal)> = account.open_positions()
+            .filter(|position| account.margin_type(&position.symbol) == MarginType::Cross)
+            .filter(|position| self.settlement_asset(&position.symbol) == asset)
+            .map(|position| {
+                let mark = mark_prices.get(&position.symbol).unwrap_or(&position.entry_price).clone();
+                (position.symbol.clone(), position.position_side, position.side, position.quantity.clone(), mark)
+            })
+            .collect();
+        positions.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+
+        let total_notional: BigDecimal = positions.iter().map(|(_, _, _, quantity, mark)| quantity * mark).sum();
+        if total_notional <= BigDecimal::from(0) {
+            return Ok(MatchResult::default());
+        }
+
+        let mut result = MatchResult::default();
+        for (symbol, position_side, side, quantity, mark) in positions {
+            let share = &equity * (&quantity * &mark) / &total_notional;
+            let bankruptcy_price = match side {
+                Side::Buy => &mark - share / &quantity,
+                Side::Sell => &mark + share / &quantity,
+            };
+            result.extend(self.liquidate_position(user_id, &symbol, position_side, &mark, bankruptcy_price)?);
+        }
+        Ok(result)
+    }
+
+    /**
+     * cancels the user's open orders on the position's leg of symbol, then closes the whole position:
+     * first into the book, no worse than the insurance fund can make good, and whatever the book
+     * could not take by auto-deleveraging profitable opposite positions at the bankruptcy price
+     */
+    fn liquidate_position(
+        &mut self,
+        user_id: Uuid,
+        symbol: &str,
+        position_side: Option<PositionSide>,
+        mark_price: &BigDecimal,
+        bankruptcy_price: BigDecimal,
+    ) -> Result<MatchResult, OrderError> {
+        if let Some(order_book) = self.order_books.get(symbol) {
+            let mut open_orders: Vec<Uuid> = order_book.orders.values()
+                .chain(order_book.stop_orders.values())
+                .filter(|order| order.user_id == user_id && order.position_side == position_side)
+                .map(|order| order.id)
+                .collect();
+            open_orders.sort();
+            for order_id in open_orders {
+                self.release_order(symbol, order_id)?;
+            }
+        }
+
+        let position = match self.get_account(user_id)?.position(symbol, position_side) {
+            Some(position) => position.clone(),
+            None => return Ok(MatchResult::default()),
+        };
+
+        // the fund can pay for fills up to its balance spread over the position past the bankruptcy price
+        let asset = self.settlement_asset(symbol);
+        let cushion = self.insurance_fund_balance(&asset) / &position.quantity;
+        let limit_price = match position.side {
+            Side::Buy => (&bankruptcy_price - cushion).max(BigDecimal::from(0)),
+            Side::Sell => &bankruptcy_price + cushion,
+        };
+
+        let now = self.clock.now();
+        let sequence = self.order_books.get(symbol).map_or(0, |order_book| order_book.sequence);
+        let order = Order {
+            id: OrderBook::sequenced_id(symbol, "liquidation", sequence),
+            user_id,
+            symbol: symbol.to_string(),
+            side: position.side.opposite(),
+            order_type: OrderType::Limit,
+            price: limit_price,
+            trigger_price: None,
+            trigger_by: TriggerBy::MarkPrice,
+            quantity: position.quantity.clone(),
+            filled_quantity: BigDecimal::from(0),
+            display_quantity: None,
+            leverage: position.leverage.clone(),
+            time_in_force: TimeInForce::IOC,
+            post_only: None,
+            reduce_only: true,
+            self_trade_prevention: None,
+            created_at: now,
+            updated_at: now,
+            sequence: 0,
+            position_side,
+        };
+        let order_id = order.id;
+        let mut result = self.execute_order(order)?;
+
+        let fills: Vec<&Trade> = result.trades.iter()
+            .filter(|trade| trade.buyer_order_id == order_id || trade.seller_order_id == order_id)
+            .collect();
+        let filled: BigDecimal = fills.iter().map(|trade| trade.quantity.clone()).sum();
+        let fill_price = if filled > BigDecimal::from(0) {
+            let notional: BigDecimal = fills.iter().map(|trade| &trade.price * &trade.quantity).sum();
+            Some(notional / &filled)
+        } else {
+            None
+        };
+
+        // the fills already settled the trading loss, the trader is left owing exactly the margin of
+        // what closed: anything better than the bankruptcy price goes to the fund, anything worse
+        // is paid by it as far as it can
+        let beyond_bankruptcy = match (&fill_price, position.side) {
+            (Some(fill_price), Side::Buy) => (fill_price - &bankruptcy_price) * &filled,
+            (Some(fill_price), Side::Sell) => (&bankruptcy_price - fill_price) * &filled,
+            (None, _) => BigDecimal::from(0),
+        };
+        let surplus = beyond_bankruptcy.clone().max(BigDecimal::from(0));
+        let deficit = (-beyond_bankruptcy).max(BigDecimal::from(0));
+
+        let fund = self.insurance_funds.entry(asset.clone()).or_default();
+        if surplus > BigDecimal::from(0) {
+            fund.credit(InsuranceFundReason::LiquidationSurplus, surplus.clone(), Some(user_id), Some(symbol.to_string()), now);
+        }
+        let insurance_payout = fund.cover(&deficit, user_id, symbol.to_string(), now);
+        let reference = LedgerReference::Liquidation { symbol: symbol.to_string(), user_id };
+        self.post(
+            LedgerAccount::User(user_id),
+            LedgerAccount::InsuranceFund,
+            &asset,
+            surplus.clone(),
+            LedgerReason::LiquidationSurplus,
+            Some(reference.clone()),
+        )?;
+        self.post(
+            LedgerAccount::InsuranceFund,
+            LedgerAccount::User(user_id),
+            &asset,
+            insurance_payout.clone(),
+            LedgerReason::InsurancePayout,
+            Some(reference),
+        )?;
+
+        let unfilled = &position.quantity - &filled;
+        let deleverages = self.auto_deleverage(user_id, symbol, position_side, position.side, &unfilled, &bankruptcy_price)?;
+        let deleveraged: BigDecimal = deleverages.iter().map(|event| event.quantity.clone()).sum();
+        result.deleverages.extend(deleverages);
+
+        result.liquidations.push(LiquidationEvent {
+            symbol: symbol.to_string(),
+            user_id,
+            order_id,
+            side: position.side,
+            position_side,
+            quantity: position.quantity.clone(),
+            mark_price: mark_price.clone(),
+            bankruptcy_price,
+            fill_price,
+            remaining_quantity: unfilled - &deleveraged,
+            deleveraged_quantity: deleveraged,
+            surplus,
+            deficit,
+            insurance_payout,
+            liquidated_at: now,
+        });
+        Ok(result)
+    }
+
+    /**
+     * closes up to quantity of a bankrupt position against the top of the ADL queue,
+     * both sides at the bankruptcy price
+     */
+    fn auto_deleverage(
+        &mut self,
+        bankrupt_user_id: Uuid,
+        symbol: &str,
+        bankrupt_position_side: Option<PositionSide>,
+        side: Side,
+        quantity: &BigDecimal,
+        bankruptcy_price: &BigDecimal,
+    ) -> Result<Vec<AdlEvent>, OrderError> {
+        let mut events = Vec::new();
+        let mut remaining = quantity.clone();
+        let asset = self.settlement_asset(symbol);
+        let now = self.clock.now();
+
+        for (user_id, position_side, _) in self.adl_queue(symbol, side.opposite()) {
+            if remaining <= BigDecimal::from(0) {
+                break;
+            }
+            if user_id == bankrupt_user_id {
+                continue;
+            }
+            let held = match self.accounts[&user_id].position(symbol, position_side) {
+                Some(position) => position.quantity.clone(),
+                None => continue,
+            };
+            let closed = remaining.clone().min(held);
+
+            let parties = [
+                (bankrupt_user_id, bankrupt_position_side, side.opposite()),
+                (user_id, position_side, side),
+            ];
+            for (party, party_position_side, closing_side) in parties {
+                let realized = self.get_account(party)?
+                    .realized_pnl(symbol, party_position_side, closing_side, &closed, bankruptcy_price);
+                self.post(
+                    LedgerAccount::Settlement,
+                    LedgerAccount::User(party),
+                    &asset,
+                    realized.clone(),
+                    LedgerReason::Deleverage,
+                    Some(LedgerReference::Deleverage { symbol: symbol.to_string(), bankrupt_user_id }),
+                )?;
+                let account = self.get_account(party)?;
+                let position = account.position_mut(symbol, party_position_side).unwrap();
+                position.realized_pnl += &realized;
+                let position = position.clone();
+                account.update_position(
+                    symbol.to_string(),
+                    party_position_side,
+                    closing_side,
+                    &closed,
+                    bankruptcy_price,
+                    position.position_type,
+                    &position.leverage,
+                    &position.margin_type,
+                    true,
+                    now,
+                )?;
+
+                if party == user_id {
+                    events.push(AdlEvent {
+                        symbol: symbol.to_string(),
+                        user_id,
+                        bankrupt_user_id,
+                        side: side.opposite(),
+                        position_side,
+                        quantity: closed.clone(),
+                        price: bankruptcy_price.clone(),
+                        realized_pnl: realized,
+                        deleveraged_at: now,
+                    });
+                }
+            }
+            self.trim_reduce_only_orders(user_id, symbol);
+            remaining -= closed;
+        }
+
+        Ok(events)
+    }
+
+    /**
+     * profitable positions on one side of symbol by user and leg, first to be deleveraged first
+     * ranked by unrealized PnL at the mark price times effective leverage, i.e. notional over
+     * margin plus unrealized PnL, ties broken by user id
+     */
+    fn adl_queue(&self, symbol: &str, side: Side) -> Vec<(Uuid, Option<PositionSide>, BigDecimal)> {
+        let mark_price = match self.market_data.get(symbol) {
+            Some(market_data) => &market_data.mark_price,
+            None => return Vec::new(),
+        };
+
+        let mut queue: Vec<(Uuid, Option<PositionSide>, BigDecimal)> = self.accounts.values()
+            .flat_map(|account| account.open_positions_on(symbol))
+            .filter(|position| position.side == side)
+            .filter_map(|position| {
+                let pnl = match side {
+                    Side::Buy => (mark_price - &position.entry_price) * &position.quantity,
+                    Side::Sell => (&position.entry_price - mark_price) * &position.quantity,
+                };
+                if pnl <= BigDecimal::from(0) {
+                    return None;
+                }
+                let leverage = position.leverage.clone().unwrap_or(BigDecimal::from(1));
+                let margin = &position.entry_price * &position.quantity / leverage;
+                let effective_leverage = mark_price * &position.quantity / (margin + &pnl);
+                Some((position.user_id, position.position_side, pnl * effective_leverage))
+            })
+            .collect();
+        queue.sort_by(|(a_user, a_leg, a_score), (b_user, b_leg, b_score)| {
+            b_score.cmp(a_score).then(a_user.cmp(b_user)).then(a_leg.cmp(b_leg))
+        });
+        queue
+    }
+
+    /**
+     * where a user's position on symbol stands in the ADL queue, 1 to 5 with 5 deleveraged first
+     * None when the position is not profitable and so not in the queue
+     * in hedge mode the leg closest to the front counts
+     */
+    pub fn adl_quantile(&self, user_id: Uuid, symbol: &str) -> Option<u8> {
+        self.accounts.get(&user_id)?.open_positions_on(symbol)
+            .filter_map(|position| {
+                let queue = self.adl_queue(symbol, position.side);
+                let rank = queue.iter()
+                    .position(|(queued, leg, _)| *queued == user_id && *leg == position.position_side)?;
+                Some(5 - (rank * 5 / queue.len()) as u8)
+            })
+            .max()
+    }
+
+    pub fn place_order(&mut self, mut order: Order) -> Result<MatchResult, OrderError> {
+        self.record(|| Command::PlaceOrder { order: order.clone() })?;
+
+        let spec = self.contract_specs.get(&order.symbol)
+            .ok_or(OrderError::UnknownSymbol)?;
+
         let market_data = self.market_data.get(&order.symbol)
-            .ok_or(OrderError::InvalidOrder)?
+            .ok_or(OrderError::UnknownSymbol)?
             .clone();
 
-        if Utc::now() - market_data.last_update > chrono::Duration::seconds(30) {
-            return Err(OrderError::InvalidOrder);
+        if self.clock.now() - market_data.last_update > chrono::Duration::seconds(30) {
+            return Err(OrderError::StaleMarketData);
         }
 
-        let quote_asset = self.quote_asset.clone();
+        spec.validate_order(&order, &market_data.mark_price)?;
+
+        // an order without leverage pays its full notional, so it is held, checked and liquidated at 1x
+        order.leverage.get_or_insert_with(|| BigDecimal::from(1));
+
+        if order.time_in_force.expires_at().is_some_and(|expiry| expiry <= self.clock.now()) {
+            return Err(OrderError::AlreadyExpired);
+        }
+
+        let quote_asset = self.settlement_asset(&order.symbol);
+        let mark_prices = self.mark_prices();
+        let available = self.available_balance(order.user_id, &quote_asset)?;
         let account = self.get_account(order.user_id)?;
+        let margin_type = account.margin_type(&order.symbol);
 
-        account.check_margin_requirements(
-            &order,
-            &market_data.mark_price,
-            Some(MarginType::Isolated),
-        )?;
+        // hedge mode orders name their leg, and the ones on the leg's closing side can only reduce it
+        match (account.position_mode, order.position_side) {
+            (PositionMode::OneWay, None) => {}
+            (PositionMode::Hedge, Some(position_side)) => {
+                if matches!((order.side, position_side), (Side::Sell, PositionSide::Long) | (Side::Buy, PositionSide::Short)) {
+                    order.reduce_only = true;
+                }
+            }
+            _ => return Err(OrderError::InvalidPositionSide),
+        }
 
-        if let Some(leverage) = &order.leverage {
-            let required_margin = MarginCalculator::calculate_required_margin(
-                &order.quantity,
-                &order.price,
-                leverage,
-                MarginType::Isolated,
-            );
-            let balance = account.withdraw(quote_asset);
-            if balance < required_margin {
-                return Err(OrderError::InsufficientBalance);
+        let margin_price = Self::margin_price(&order, &market_data.mark_price);
+        let required_margin = order.leverage.as_ref().map(|leverage| MarginCalculator::calculate_required_margin(
+            &order.quantity,
+            &margin_price,
+            leverage,
+            margin_type,
+        ));
+
+        match margin_type {
+            MarginType::Isolated => {
+                account.check_margin_requirements(
+                    &order,
+                    &market_data.mark_price,
+                    Some(MarginType::Isolated),
+                )?;
+                if required_margin.as_ref().is_some_and(|required| &available < required) {
+                    return Err(OrderError::InsufficientBalance);
+                }
+            }
+            // cross orders draw on what the account's cross equity has left after its open positions and orders
+            MarginType::Cross => {
+                let available = account.cross_available(&quote_asset, &mark_prices);
+                if required_margin.as_ref().is_some_and(|required| &available < required) {
+                    return Err(OrderError::InsufficientBalance);
+                }
+            }
+        }
+
+        if order.reduce_only {
+            self.cap_reduce_only(&mut order)?;
+        }
+
+        let (order_id, user_id, symbol, quantity) = (order.id, order.user_id, order.symbol.clone(), order.quantity.clone());
+        let mut result = self.execute_order(order)?;
+
+        // whatever did not fill straight away keeps its share of the margin on hold
+        if let Some(required_margin) = required_margin {
+            if let Some(remaining) = self.get_order(&symbol, order_id).map(|o| o.remaining_quantity()) {
+                let amount = required_margin * &remaining / quantity;
+                self.get_account(user_id)?.hold_margin(order_id, symbol.clone(), quote_asset, remaining, amount);
             }
         }
 
-        let order_book = self.order_books.get_mut(&order.symbol).unwrap();
-        let trades = order_book.add_order(order)?;
+        result.extend(self.trigger_stop_orders(&symbol)?);
+
+        Ok(result)
+    }
+
+    /**
+     * price an order's margin is held at: market orders at the mark price, stop market orders at
+     * their trigger, everything else at its limit price
+     */
+    fn margin_price(order: &Order, mark_price: &BigDecimal) -> BigDecimal {
+        match order.order_type {
+            OrderType::Market => mark_price.clone(),
+            OrderType::Stop => order.trigger_price.clone().unwrap_or(mark_price.clone()),
+            _ => order.price.clone(),
+        }
+    }
+
+    /**
+     * runs an already validated order through the book and settles its fills
+     */
+    fn execute_order(&mut self, order: Order) -> Result<MatchResult, OrderError> {
+        let (order_id, user_id, symbol, reduce_only) = (order.id, order.user_id, order.symbol.clone(), order.reduce_only);
+        let mut limits = self.reduce_only_limits(&symbol, order.side);
+        let order_book = self.order_books.get_mut(&symbol).unwrap();
+        let mut result = order_book.add_order_with_limits(order, &mut limits)?;
+        // a refused order, a duplicate id among them, must not touch the entry of the one already resting
+        if reduce_only && order_book.get_order(order_id).is_some() {
+            self.reduce_only_orders.insert(order_id, (user_id, symbol.clone()));
+        }
+
+        self.settle_trades(user_id, &symbol, &mut result)?;
+        Ok(result)
+    }
 
-        for trade in &trades {
+    /**
+     * applies a match to positions, fees and the last trade price,
+     * then re-checks reduce-only orders of everyone whose position moved
+     */
+    fn settle_trades(&mut self, user_id: Uuid, symbol: &str, result: &mut MatchResult) -> Result<(), OrderError> {
+        for trade in result.trades.iter_mut() {
             self.process_trade(trade)?;
+            self.charge_fees(trade)?;
             self.last_trade_prices.insert(trade.symbol.clone(), trade.price.clone());
         }
 
-        Ok(trades)
+        let mut touched_users = vec![user_id];
+        for trade in &result.trades {
+            touched_users.push(trade.buyer_user_id);
+            touched_users.push(trade.seller_user_id);
+        }
+        touched_users.extend(result.cancelled.iter().map(|order| order.user_id));
+        touched_users.sort();
+        touched_users.dedup();
+        for user_id in touched_users {
+            self.trim_reduce_only_orders(user_id, symbol);
+            self.sync_holds(user_id, symbol)?;
+        }
+
+        Ok(())
     }
 
-    fn process_trade(&mut self, trade: &Trade) -> Result<(), OrderError> {
-        let order_book = self.order_books.get(&trade.symbol).unwrap();
-        let buyer_order = order_book.bids.iter()
-            .find(|o| o.id == trade.buyer_order_id)
-            .ok_or(OrderError::OrderNotFound)?
-            .clone();
-        let seller_order = order_book.asks.iter()
-            .find(|o| o.id == trade.seller_order_id)
-            .ok_or(OrderError::OrderNotFound)?
-            .clone();
+    /**
+     * shrinks a user's order holds on symbol to what their orders still have open
+     * fills, trims and orders the book cancelled on its own all end up here
+     */
+    fn sync_holds(&mut self, user_id: Uuid, symbol: &str) -> Result<(), OrderError> {
+        let order_book = self.order_books.get(symbol)
+            .ok_or(OrderError::UnknownSymbol)?;
+        let account = self.accounts.get_mut(&user_id)
+            .ok_or(OrderError::OrderNotFound)?;
 
-        {
-            let buyer_account = self.get_account(buyer_order.user_id)?;
-            buyer_account.update_position(
-                trade.symbol.clone(),
-                Side::Buy,
-                &trade.quantity,
-                &trade.price,
-                PositionType::Margin,
-                &buyer_order.leverage,
-                &Some(MarginType::Isolated),
-            )?;
-            if let Some(position) = buyer_account.positions.get_mut(&trade.symbol) {
-                if position.quantity > BigDecimal::from(0) {
-                    let pnl = (trade.price.clone() - position.entry_price.clone()) * position.quantity.clone();
-                    if let Some(margin) = &mut position.margin {
-                        *margin = margin.clone() + pnl;
-                    }
+        let order_ids: Vec<Uuid> = account.order_holds.iter()
+            .filter(|(_, hold)| hold.symbol == symbol)
+            .map(|(order_id, _)| *order_id)
+            .collect();
+        for order_id in order_ids {
+            let remaining = order_book.get_order(order_id)
+                .map(|order| order.remaining_quantity())
+                .unwrap_or(BigDecimal::from(0));
+            account.release_hold(order_id, &remaining);
+        }
+        Ok(())
+    }
+
+    /**
+     * the open position a reduce-only order on this side and leg is allowed to close, zero if none
+     */
+    fn reducible_quantity(&self, user_id: Uuid, symbol: &str, side: Side, position_side: Option<PositionSide>) -> BigDecimal {
+        self.accounts.get(&user_id)
+            .and_then(|account| account.position(symbol, position_side))
+            .filter(|position| position.side != side)
+            .map(|position| position.quantity.clone())
+            .unwrap_or(BigDecimal::from(0))
+    }
+
+    /**
+     * what the resting reduce-only orders an incoming order on taker_side could hit may still close,
+     * per user and leg, for the book to hold them to while matching
+     */
+    fn reduce_only_limits(&self, symbol: &str, taker_side: Side) -> ReduceOnlyLimits {
+        let order_book = match self.order_books.get(symbol) {
+            Some(order_book) => order_book,
+            None => return ReduceOnlyLimits::new(),
+        };
+        self.reduce_only_orders.iter()
+            .filter(|(_, (_, order_symbol))| order_symbol == symbol)
+            .filter_map(|(order_id, _)| order_book.get_order(*order_id))
+            .filter(|order| order.side != taker_side)
+            .map(|order| (
+                (order.user_id, order.position_side),
+                self.reducible_quantity(order.user_id, symbol, order.side, order.position_side),
+            ))
+            .collect()
+    }
+
+    /**
+     * shrinks a reduce-only order to the size of the position it closes
+     */
+    fn cap_reduce_only(&self, order: &mut Order) -> Result<(), OrderError> {
+        let reducible = self.reducible_quantity(order.user_id, &order.symbol, order.side, order.position_side);
+        if reducible <= BigDecimal::from(0) {
+            return Err(OrderError::ReduceOnlyWouldIncrease);
+        }
+        if order.remaining_quantity() > reducible {
+            order.quantity = order.filled_quantity.clone() + reducible;
+        }
+        Ok(())
+    }
+
+    /**
+     * keeps a user's open reduce-only orders on a symbol within their position after it changes
+     * each order is trimmed to the position size, and cancelled once there is nothing left to reduce
+     */
+    fn trim_reduce_only_orders(&mut self, user_id: Uuid, symbol: &str) {
+        let mut order_ids: Vec<Uuid> = self.reduce_only_orders.iter()
+            .filter(|(_, (owner, order_symbol))| *owner == user_id && order_symbol == symbol)
+            .map(|(order_id, _)| *order_id)
+            .collect();
+        // each cancel or trim takes the next book sequence, so hand them out in an order replay repeats
+        order_ids.sort();
+
+        for order_id in order_ids {
+            let order = match self.order_books.get(symbol).and_then(|b| b.get_order(order_id)) {
+                Some(order) => order.clone(),
+                None => {
+                    self.reduce_only_orders.remove(&order_id);
+                    continue;
+                }
+            };
+
+            let reducible = self.reducible_quantity(user_id, symbol, order.side, order.position_side);
+            let order_book = self.order_books.get_mut(symbol).unwrap();
+            if reducible <= BigDecimal::from(0) {
+                let _ = order_book.cancel_order(order_id);
+                self.reduce_only_orders.remove(&order_id);
+            } else if order.remaining_quantity() > reducible {
+                let _ = order_book.reduce_order(order_id, order.filled_quantity.clone() + reducible);
+            }
+        }
+    }
+
+    /**
+     * fires every stop on the symbol whose last trade or mark price trigger has been crossed
+     * fills from fired stops move the last price and can fire more, so repeat until none fire
+     */
+    fn trigger_stop_orders(&mut self, symbol: &str) -> Result<MatchResult, OrderError> {
+        let mut result = MatchResult::default();
+
+        loop {
+            let last_price = self.last_trade_prices.get(symbol)
+                .cloned()
+                .unwrap_or(BigDecimal::from(0));
+            let mark_price = self.market_data.get(symbol)
+                .map(|m| m.mark_price.clone())
+                .unwrap_or(BigDecimal::from(0));
+
+            let fired = match self.order_books.get_mut(symbol) {
+                Some(order_book) => order_book.trigger_stops(&last_price, &mark_price),
+                None => break,
+            };
+            if fired.is_empty() {
+                break;
+            }
+
+            for mut order in fired {
+                // the position may have changed since the stop was placed
+                if order.reduce_only && self.cap_reduce_only(&mut order).is_err() {
+                    self.reduce_only_orders.remove(&order.id);
+                    self.get_account(order.user_id)?.release_hold(order.id, &BigDecimal::from(0));
+                    continue;
                 }
+                result.extend(self.execute_order(order)?);
             }
         }
 
-        {
-            let seller_account = self.get_account(seller_order.user_id)?;
-            seller_account.update_position(
+        Ok(result)
+    }
+
+    /**
+     * charges maker and taker fees in the symbol's quote asset and records them on the trade
+     * both sides are priced on their volume before this fill, which then counts towards their tier
+     */
+    fn charge_fees(&mut self, trade: &mut Trade) -> Result<(), OrderError> {
+        let fee_asset = self.settlement_asset(&trade.symbol);
+        let notional = &trade.price * &trade.quantity;
+        let buyer_is_maker = trade.aggressor_side == Side::Sell;
+
+        trade.buyer_fee = self.fee_engine.fee_for(trade.buyer_user_id, &trade.symbol, &notional, buyer_is_maker, trade.executed_at);
+        trade.seller_fee = self.fee_engine.fee_for(trade.seller_user_id, &trade.symbol, &notional, !buyer_is_maker, trade.executed_at);
+        trade.fee_asset = Some(fee_asset.clone());
+
+        let sides = [
+            (trade.buyer_user_id, trade.buyer_position_side, trade.buyer_fee.clone()),
+            (trade.seller_user_id, trade.seller_position_side, trade.seller_fee.clone()),
+        ];
+        for (user_id, position_side, fee) in sides {
+            if let Some(position) = self.get_account(user_id)?.position_mut(&trade.symbol, position_side) {
+                position.fees += &fee;
+            }
+            self.post(
+                LedgerAccount::User(user_id),
+                LedgerAccount::FeeRevenue,
+                &fee_asset,
+                fee,
+                LedgerReason::Fee,
+                Some(LedgerReference::Trade(trade.id)),
+            )?;
+        }
+        self.fee_engine.record_volume(trade.buyer_user_id, notional.clone(), trade.executed_at);
+        self.fee_engine.record_volume(trade.seller_user_id, notional, trade.executed_at);
+        Ok(())
+    }
+
+    /**
+     * moves both positions and pays out what the fill realized in the settlement asset
+     */
+    fn process_trade(&mut self, trade: &Trade) -> Result<(), OrderError> {
+        let asset = self.settlement_asset(&trade.symbol);
+        let mark_price = self.market_data.get(&trade.symbol)
+            .map(|market_data| market_data.mark_price.clone())
+            .unwrap_or(trade.price.clone());
+
+        let sides = [
+            (trade.buyer_user_id, trade.buyer_position_side, Side::Buy, &trade.buyer_order_id, &trade.buyer_leverage),
+            (trade.seller_user_id, trade.seller_position_side, Side::Sell, &trade.seller_order_id, &trade.seller_leverage),
+        ];
+        for (user_id, position_side, side, order_id, leverage) in sides {
+            let reduce_only = self.reduce_only_orders.contains_key(order_id);
+            let realized = self.get_account(user_id)?
+                .realized_pnl(&trade.symbol, position_side, side, &trade.quantity, &trade.price);
+            self.post(
+                LedgerAccount::Settlement,
+                LedgerAccount::User(user_id),
+                &asset,
+                realized.clone(),
+                LedgerReason::RealizedPnl,
+                Some(LedgerReference::Trade(trade.id)),
+            )?;
+
+            let account = self.get_account(user_id)?;
+            if let Some(position) = account.position_mut(&trade.symbol, position_side) {
+                position.realized_pnl += realized;
+            }
+            let margin_type = account.margin_type(&trade.symbol);
+            account.update_position(
                 trade.symbol.clone(),
-                Side::Sell,
+                position_side,
+                side,
                 &trade.quantity,
                 &trade.price,
                 PositionType::Margin,
-                &seller_order.leverage,
-                &Some(MarginType::Isolated),
+                leverage,
+                &Some(margin_type),
+                reduce_only,
+                trade.executed_at,
             )?;
-            if let Some(position) = seller_account.positions.get_mut(&trade.symbol) {
-                if position.quantity > BigDecimal::from(0) {
-                    let pnl = (position.entry_price.clone() - trade.price.clone()) * position.quantity.clone();
-                    if let Some(margin) = &mut position.margin {
-                        *margin = margin.clone() + pnl;
-                    }
-                }
-            }
+            account.mark_to_market(&trade.symbol, &mark_price);
         }
 
         Ok(())
     }
 
+    /**
+     * cancels a user's open order, the returned order carries the cancel's book sequence
+     */
     pub fn cancel_order(
         &mut self,
         user_id: Uuid,
         symbol: String,
         order_id: Uuid,
-        side: Side,
-    ) -> Result<(), OrderError> {
-        let quote_asset = self.quote_asset.clone();
-        let order_book = self.order_books.get_mut(&symbol)
-            .ok_or(OrderError::InvalidOrder)?;
+    ) -> Result<Order, OrderError> {
+        self.record(|| Command::CancelOrder { user_id, symbol: symbol.clone(), order_id })?;
+
+        let order_book = self.order_books.get(&symbol)
+            .ok_or(OrderError::UnknownSymbol)?;
+
+        // only the owner may cancel, everyone else sees no such order
+        if order_book.get_order(order_id).is_none_or(|o| o.user_id != user_id) {
+            return Err(OrderError::OrderNotFound);
+        }
+        self.release_order(&symbol, order_id)
+    }
 
-        let order = match side {
-            Side::Buy => order_book.bids.iter().find(|o| o.id == order_id),
-            Side::Sell => order_book.asks.iter().find(|o| o.id == order_id),
-        }.ok_or(OrderError::OrderNotFound)?
-        .clone();
+    /**
+     * cancels every GTD order whose expiry has passed on the exchange clock and releases its margin
+     */
+    pub fn expire_orders(&mut self) -> Result<Vec<Order>, OrderError> {
+        self.record(|| Command::ExpireOrders)?;
+        let now = self.clock.now();
+        let mut expired = Vec::new();
 
-        order_book.cancel_order(order_id, side)?;
+        for symbol in self.symbols.clone() {
+            let order_ids = match self.order_books.get_mut(&symbol) {
+                Some(order_book) => order_book.take_expired(now),
+                None => continue,
+            };
+            for order_id in order_ids {
+                expired.push(self.release_order(&symbol, order_id)?);
+            }
+        }
 
-        if let Some(leverage) = &order.leverage {
-            let account = self.get_account(user_id)?;
-            let required_margin = MarginCalculator::calculate_required_margin(
-                &order.quantity,
-                &order.price,
+        Ok(expired)
+    }
+
+    /**
+     * takes an order off the book and frees the margin held for it
+     */
+    fn release_order(&mut self, symbol: &str, order_id: Uuid) -> Result<Order, OrderError> {
+        let order_book = self.order_books.get_mut(symbol)
+            .ok_or(OrderError::UnknownSymbol)?;
+        let order = order_book.cancel_order(order_id)?;
+        self.reduce_only_orders.remove(&order_id);
+        self.get_account(order.user_id)?.release_hold(order_id, &BigDecimal::from(0));
+        Ok(order)
+    }
+
+    /**
+     * changes price and/or quantity of a user's open order, see OrderBook::amend_order for priority rules
+     * the amended order is validated and its margin re-checked before the book is touched,
+     * so a rejected amend leaves the original order as it was
+     */
+    pub fn amend_order(
+        &mut self,
+        user_id: Uuid,
+        symbol: String,
+        order_id: Uuid,
+        price: Option<BigDecimal>,
+        quantity: Option<BigDecimal>,
+    ) -> Result<MatchResult, OrderError> {
+        self.record(|| Command::AmendOrder {
+            user_id,
+            symbol: symbol.clone(),
+            order_id,
+            price: price.clone(),
+            quantity: quantity.clone(),
+        })?;
+
+        let spec = self.contract_specs.get(&symbol)
+            .ok_or(OrderError::UnknownSymbol)?;
+        let order_book = self.order_books.get(&symbol)
+            .ok_or(OrderError::UnknownSymbol)?;
+        let current = order_book.get_order(order_id)
+            .filter(|o| o.user_id == user_id)
+            .ok_or(OrderError::OrderNotFound)?
+            .clone();
+
+        let mut amended = current.clone();
+        if let Some(price) = &price {
+            amended.price = price.clone();
+        }
+        if let Some(quantity) = &quantity {
+            amended.quantity = quantity.clone();
+        }
+        let mark_price = self.market_data.get(&symbol)
+            .map(|m| m.mark_price.clone())
+            .unwrap_or(BigDecimal::from(0));
+        spec.validate_order(&amended, &mark_price)?;
+
+        if amended.reduce_only {
+            self.cap_reduce_only(&mut amended)?;
+        }
+
+        // only the extra margin an amend asks for needs to be covered, the current hold already is
+        let asset = self.settlement_asset(&symbol);
+        let mut new_hold = None;
+        if let Some(leverage) = &amended.leverage {
+            let account = self.accounts.get(&user_id)
+                .ok_or(OrderError::OrderNotFound)?;
+            let margin_type = account.margin_type(&symbol);
+            let margin_for = |o: &Order| MarginCalculator::calculate_required_margin(
+                &o.remaining_quantity(),
+                &Self::margin_price(o, &mark_price),
                 leverage,
-                MarginType::Isolated,
+                margin_type,
             );
-            account.deposit(quote_asset, required_margin);
+            let held = account.order_holds.get(&order_id)
+                .map(|hold| hold.amount.clone())
+                .unwrap_or(BigDecimal::from(0));
+            let extra_margin = margin_for(&amended) - held;
+            let available = match margin_type {
+                MarginType::Isolated => self.available_balance(user_id, &asset)?,
+                MarginType::Cross => account.cross_available(&asset, &self.mark_prices()),
+            };
+            if extra_margin > BigDecimal::from(0) && available < extra_margin {
+                return Err(OrderError::InsufficientBalance);
+            }
+            new_hold = Some(margin_for(&amended));
         }
 
-        Ok(())
+        let mut limits = self.reduce_only_limits(&symbol, amended.side);
+        let order_book = self.order_books.get_mut(&symbol).unwrap();
+        let mut result = order_book.amend_order_with_limits(
+            order_id,
+            Some(amended.price.clone()),
+            Some(amended.quantity.clone()),
+            &mut limits,
+        )?;
+        if order_book.get_order(order_id).is_none() {
+            self.reduce_only_orders.remove(&order_id);
+        }
+        if let Some(amount) = new_hold {
+            self.get_account(user_id)?.hold_margin(order_id, symbol.clone(), asset, amended.remaining_quantity(), amount);
+        }
+
+        self.settle_trades(user_id, &symbol, &mut result)?;
+        result.extend(self.trigger_stop_orders(&symbol)?);
+        Ok(result)
     }
 
     pub fn run_funding(&mut self) -> Result<Vec<FundingRate>, OrderError> {
+        self.record(|| Command::RunFunding)?;
         let mut new_rates = Vec::new();
 
-        for symbol in &self.symbols {
-            let market_data = self.market_data.get(symbol)
-                .ok_or(OrderError::InvalidOrder)?;
+        for symbol in self.symbols.clone() {
+            let asset = self.settlement_asset(&symbol);
+            let market_data = self.market_data.get(&symbol)
+                .ok_or(OrderError::UnknownSymbol)?;
 
-            if Utc::now() - market_data.last_update > chrono::Duration::seconds(30) {
-                return Err(OrderError::InvalidOrder);
+            if self.clock.now() - market_data.last_update > chrono::Duration::seconds(30) {
+                return Err(OrderError::StaleMarketData);
             }
 
             let rate = self.funding_calculator.calculate_funding_rate(
@@ -243,8 +1550,29 @@ impl Exchange {
                 &market_data.open_interest_short,
             );
 
-            for account in self.accounts.values_mut() {
-                self.funding_calculator.apply_funding(&mut account.positions, &rate)?;
+            // fixed account order keeps the payment history identical on replay
+            let mut user_ids: Vec<Uuid> = self.accounts.keys().copied().collect();
+            user_ids.sort();
+            for user_id in user_ids {
+                let payments = self.funding_calculator.apply_funding(&self.accounts[&user_id].positions, &rate);
+                // longs pay a positive rate to the funding account and shorts collect it from there
+                for payment in payments {
+                    let amount = match payment.side {
+                        Side::Buy => -payment.payment,
+                        Side::Sell => payment.payment,
+                    };
+                    if let Some(position) = self.get_account(user_id)?.position_mut(&symbol, payment.position_side) {
+                        position.funding += &amount;
+                    }
+                    self.post(
+                        LedgerAccount::Funding,
+                        LedgerAccount::User(user_id),
+                        &asset,
+                        amount,
+                        LedgerReason::Funding,
+                        Some(LedgerReference::Funding { symbol: symbol.clone() }),
+                    )?;
+                }
             }
 
             new_rates.push(rate);
@@ -253,6 +1581,10 @@ impl Exchange {
         Ok(new_rates)
     }
 
+    pub fn get_order(&self, symbol: &str, order_id: Uuid) -> Option<&Order> {
+        self.order_books.get(symbol)?.get_order(order_id)
+    }
+
     pub fn get_market_data(&self, symbol: &str) -> Option<&MarketData> {
         self.market_data.get(symbol)
     }
@@ -0,0 +1,141 @@
+use crate::models::OrderError;
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use uuid::Uuid;
+
+/**
+ * days of traded notional that count towards a user's fee tier
+ */
+const VOLUME_WINDOW_DAYS: i64 = 30;
+
+/**
+ * rates that apply once a user's rolling volume reaches min_volume
+ * rates are fractions of notional, a negative maker rate is a rebate
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeTier {
+    pub min_volume: BigDecimal,
+    pub maker_rate: BigDecimal,
+    pub taker_rate: BigDecimal,
+}
+
+/**
+ * fee tiers for one symbol, kept sorted by min_volume
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeSchedule {
+    tiers: Vec<FeeTier>,
+}
+
+impl FeeSchedule {
+    /**
+     * schedule from a list of tiers, there must be one starting at zero volume
+     */
+    pub fn new(mut tiers: Vec<FeeTier>) -> Result<Self, OrderError> {
+        tiers.sort_by(|a, b| a.min_volume.cmp(&b.min_volume));
+        match tiers.first() {
+            Some(base) if base.min_volume <= BigDecimal::from(0) => Ok(FeeSchedule { tiers }),
+            _ => Err(OrderError::InvalidFeeSchedule),
+        }
+    }
+
+    /**
+     * the same maker and taker rate for every user
+     */
+    pub fn flat(maker_rate: BigDecimal, taker_rate: BigDecimal) -> Self {
+        FeeSchedule {
+            tiers: vec![FeeTier {
+                min_volume: BigDecimal::from(0),
+                maker_rate,
+                taker_rate,
+            }],
+        }
+    }
+
+    /**
+     * highest tier the volume qualifies for
+     */
+    pub fn tier_for(&self, volume: &BigDecimal) -> &FeeTier {
+        self.tiers.iter()
+            .rev()
+            .find(|tier| &tier.min_volume <= volume)
+            .unwrap_or(&self.tiers[0])
+    }
+
+    pub fn tiers(&self) -> &[FeeTier] {
+        &self.tiers
+    }
+}
+
+/**
+ * works out trading fees from per-symbol schedules and each user's rolling 30 day volume
+ * symbols without a schedule trade for free
+ * volumes: notional traded per user, oldest first, dropped once it leaves the window
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FeeEngine {
+    schedules: HashMap<String, FeeSchedule>,
+    volumes: HashMap<Uuid, VecDeque<(DateTime<Utc>, BigDecimal)>>,
+}
+
+impl FeeEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_schedule(&mut self, symbol: String, schedule: FeeSchedule) {
+        self.schedules.insert(symbol, schedule);
+    }
+
+    pub fn get_schedule(&self, symbol: &str) -> Option<&FeeSchedule> {
+        self.schedules.get(symbol)
+    }
+
+    /**
+     * notional a user traded across all symbols in the window ending at now
+     */
+    pub fn rolling_volume(&self, user_id: Uuid, now: DateTime<Utc>) -> BigDecimal {
+        let since = now - Duration::days(VOLUME_WINDOW_DAYS);
+        self.volumes.get(&user_id)
+            .map(|fills| fills.iter()
+                .filter(|(at, _)| *at > since)
+                .map(|(_, notional)| notional.clone())
+                .sum())
+            .unwrap_or(BigDecimal::from(0))
+    }
+
+    /**
+     * fee for one side of a fill, priced on the volume traded before it
+     * positive is charged to the user, negative is paid out as a rebate
+     */
+    pub fn fee_for(
+        &self,
+        user_id: Uuid,
+        symbol: &str,
+        notional: &BigDecimal,
+        is_maker: bool,
+        now: DateTime<Utc>,
+    ) -> BigDecimal {
+        let schedule = match self.schedules.get(symbol) {
+            Some(schedule) => schedule,
+            None => return BigDecimal::from(0),
+        };
+        let tier = schedule.tier_for(&self.rolling_volume(user_id, now));
+        let rate = if is_maker { &tier.maker_rate } else { &tier.taker_rate };
+        notional * rate
+    }
+
+    /**
+     * adds a fill to the user's rolling volume and forgets fills older than the window
+     */
+    pub fn record_volume(&mut self, user_id: Uuid, notional: BigDecimal, now: DateTime<Utc>) {
+        let since = now - Duration::days(VOLUME_WINDOW_DAYS);
+        let fills = self.volumes.entry(user_id).or_default();
+        while fills.front().is_some_and(|(at, _)| *at <= since) {
+            fills.pop_front();
+        }
+        fills.push_back((now, notional));
+    }
+}
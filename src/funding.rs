@@ -1,22 +1,53 @@
-use crate::models::{FundingRate, OrderError, Position, Side, PositionType};
+use crate::clock::{system_clock, Clock};
+use crate::models::{FundingRate, Position, PositionSide, Side, PositionType};
 use bigdecimal::BigDecimal;
 use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::Arc;
+use uuid::Uuid;
 
-#[derive(Debug, Clone)]
+/**
+ * funding owed on one position, payment is paid by a long and received by a short,
+ * a negative payment the other way round
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FundingPayment {
+    pub user_id: Uuid,
     pub symbol: String,
+    pub side: Side,
+    pub position_side: Option<PositionSide>,
     pub rate: BigDecimal,
     pub payment: BigDecimal,
     pub timestamp: DateTime<Utc>,
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct FundingCalculator {
+    #[serde(with = "interval_millis")]
     funding_interval: Duration,
     funding_rate_history: Vec<FundingRate>,
     funding_payments: Vec<FundingPayment>,
     last_funding_time: DateTime<Utc>,
+    #[serde(skip, default = "system_clock")]
+    clock: Arc<dyn Clock>,
+}
+
+/**
+ * chrono durations have no serde support, the interval is stored as whole milliseconds
+ */
+mod interval_millis {
+    use chrono::Duration;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(interval: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i64(interval.num_milliseconds())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        i64::deserialize(deserializer).map(Duration::milliseconds)
+    }
 }
 
 impl FundingCalculator {
@@ -24,11 +55,19 @@ impl FundingCalculator {
      * creates a new funding calculator with specified interval
      */
     pub fn new(funding_interval: Duration) -> Self {
+        Self::with_clock(funding_interval, system_clock())
+    }
+
+    /**
+     * creates a funding calculator that reads time from the given clock
+     */
+    pub fn with_clock(funding_interval: Duration, clock: Arc<dyn Clock>) -> Self {
         FundingCalculator {
             funding_interval,
             funding_rate_history: Vec::new(),
             funding_payments: Vec::new(),
-            last_funding_time: Utc::now(),
+            last_funding_time: clock.now(),
+            clock,
         }
     }
 
@@ -70,21 +109,25 @@ impl FundingCalculator {
     }
 
     /**
-     * applies funding payments to positions
-     * updates position margins based on funding rate
-     * records funding payments in history
+     * works out the funding owed on each open margin position once the rate is due
+     * records the payments in history and returns them, settling them is up to the caller
      */
     pub fn apply_funding(
         &mut self,
-        positions: &mut HashMap<String, Position>,
+        positions: &HashMap<String, Position>,
         funding_rate: &FundingRate,
-    ) -> Result<(), OrderError> {
-        let current_time = Utc::now();
+    ) -> Vec<FundingPayment> {
+        let current_time = self.clock.now();
         if current_time < funding_rate.next_funding_time {
-            return Ok(());
+            return Vec::new();
         }
 
-        for position in positions.values_mut() {
+        // hedge legs in a fixed order so replays pay them in the same sequence
+        let mut legs: Vec<&Position> = positions.values().collect();
+        legs.sort_by_key(|position| position.position_side);
+
+        let mut payments = Vec::new();
+        for position in legs {
             if position.symbol != funding_rate.symbol 
                 || position.quantity == BigDecimal::from(0)
                 || position.position_type != PositionType::Margin {
@@ -92,33 +135,24 @@ impl FundingCalculator {
             }
 
             let position_value = position.quantity.clone() * position.entry_price.clone();
-            let funding_payment = position_value * funding_rate.rate.clone();
-
             let payment = FundingPayment {
+                user_id: position.user_id,
                 symbol: position.symbol.clone(),
+                side: position.side,
+                position_side: position.position_side,
                 rate: funding_rate.rate.clone(),
-                payment: funding_payment.clone(),
+                payment: position_value * funding_rate.rate.clone(),
                 timestamp: current_time,
             };
-            self.funding_payments.push(payment);
-
-            if let Some(margin) = &mut position.margin {
-                match position.side {
-                    Side::Buy => {
-                        *margin = margin.clone() - funding_payment;
-                    }
-                    Side::Sell => {
-                        *margin = margin.clone() + funding_payment;
-                    }
-                }
-
-                if margin.clone() < BigDecimal::from(0) {
-                    return Err(OrderError::FundingError);
-                }
-            }
+            self.funding_payments.push(payment.clone());
+            payments.push(payment);
         }
 
-        Ok(())
+        payments
+    }
+
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
     }
 
     /**
@@ -0,0 +1,80 @@
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InsuranceFundReason {
+    Seed,
+    LiquidationSurplus,
+    LiquidationDeficit,
+}
+
+/**
+ * one movement of the fund
+ * amount is signed, money in is positive; balance is the fund balance right after it
+ * user_id and symbol name the liquidation that caused it, seeds have neither
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InsuranceFundEntry {
+    pub reason: InsuranceFundReason,
+    pub amount: BigDecimal,
+    pub balance: BigDecimal,
+    pub user_id: Option<Uuid>,
+    pub symbol: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+/**
+ * money set aside in one quote asset to pay liquidation losses that exceed the trader's margin
+ * it is topped up by seeding and by liquidations that close better than their bankruptcy price
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InsuranceFund {
+    balance: BigDecimal,
+    history: Vec<InsuranceFundEntry>,
+}
+
+impl InsuranceFund {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn balance(&self) -> &BigDecimal {
+        &self.balance
+    }
+
+    pub fn history(&self) -> &[InsuranceFundEntry] {
+        &self.history
+    }
+
+    pub fn credit(
+        &mut self,
+        reason: InsuranceFundReason,
+        amount: BigDecimal,
+        user_id: Option<Uuid>,
+        symbol: Option<String>,
+        at: DateTime<Utc>,
+    ) {
+        self.balance += &amount;
+        self.history.push(InsuranceFundEntry {
+            reason,
+            amount,
+            balance: self.balance.clone(),
+            user_id,
+            symbol,
+            at,
+        });
+    }
+
+    /**
+     * pays out as much of amount as the fund holds and returns what it paid
+     */
+    pub fn cover(&mut self, amount: &BigDecimal, user_id: Uuid, symbol: String, at: DateTime<Utc>) -> BigDecimal {
+        let paid = amount.clone().min(self.balance.clone()).max(BigDecimal::from(0));
+        if paid > BigDecimal::from(0) {
+            self.credit(InsuranceFundReason::LiquidationDeficit, -paid.clone(), Some(user_id), Some(symbol), at);
+        }
+        paid
+    }
+}
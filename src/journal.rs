@@ -0,0 +1,184 @@
+use crate::contract::ContractSpec;
+use crate::fees::FeeSchedule;
+use crate::models::{MarginType, Order, PositionMode};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+/**
+ * a state-changing call on the exchange, with everything needed to make it again
+ * Genesis is always the first entry and carries the exchange configuration
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Command {
+    Genesis {
+        specs: Vec<ContractSpec>,
+        funding_interval_ms: i64,
+        quote_asset: String,
+    },
+    CreateAccount {
+        user_id: Uuid,
+    },
+    Deposit {
+        user_id: Uuid,
+        asset: String,
+        amount: BigDecimal,
+    },
+    PlaceOrder {
+        order: Order,
+    },
+    CancelOrder {
+        user_id: Uuid,
+        symbol: String,
+        order_id: Uuid,
+    },
+    AmendOrder {
+        user_id: Uuid,
+        symbol: String,
+        order_id: Uuid,
+        price: Option<BigDecimal>,
+        quantity: Option<BigDecimal>,
+    },
+    UpdateMarketData {
+        symbol: String,
+        mark_price: BigDecimal,
+        index_price: BigDecimal,
+        open_interest_long: BigDecimal,
+        open_interest_short: BigDecimal,
+    },
+    RunFunding,
+    ExpireOrders,
+    SetFeeSchedule {
+        symbol: String,
+        schedule: FeeSchedule,
+    },
+    SeedInsuranceFund {
+        asset: String,
+        amount: BigDecimal,
+    },
+    SetMarginType {
+        user_id: Uuid,
+        symbol: String,
+        margin_type: MarginType,
+    },
+    SetPositionMode {
+        user_id: Uuid,
+        position_mode: PositionMode,
+    },
+    RequestWithdrawal {
+        withdrawal_id: Uuid,
+        user_id: Uuid,
+        asset: String,
+        amount: BigDecimal,
+    },
+    ApproveWithdrawal {
+        withdrawal_id: Uuid,
+    },
+    CompleteWithdrawal {
+        withdrawal_id: Uuid,
+    },
+    RejectWithdrawal {
+        withdrawal_id: Uuid,
+        reason: String,
+    },
+    Transfer {
+        from_user_id: Uuid,
+        to_user_id: Uuid,
+        asset: String,
+        amount: BigDecimal,
+    },
+}
+
+/**
+ * one journaled command
+ * recorded_at is the exchange clock when the command arrived, replay runs it at that time again
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub sequence: u64,
+    pub recorded_at: DateTime<Utc>,
+    pub command: Command,
+}
+
+/**
+ * append-only command log on local disk, one JSON entry per line
+ * commands are written before they run, so a rejected command is journaled too and
+ * replay hits the same rejection
+ */
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    next_sequence: u64,
+}
+
+impl Journal {
+    /**
+     * opens the journal at path for appending, creating it if needed
+     * sequencing continues after the last entry already on disk
+     */
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let next_sequence = if path.exists() {
+            Self::read(&path)?.last().map_or(1, |entry| entry.sequence + 1)
+        } else {
+            1
+        };
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        Ok(Journal {
+            path,
+            file,
+            next_sequence,
+        })
+    }
+
+    /**
+     * writes a command and flushes it to disk, returns its sequence number
+     */
+    pub fn append(&mut self, recorded_at: DateTime<Utc>, command: Command) -> io::Result<u64> {
+        let entry = JournalEntry {
+            sequence: self.next_sequence,
+            recorded_at,
+            command,
+        };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()?;
+
+        self.next_sequence += 1;
+        Ok(entry.sequence)
+    }
+
+    /**
+     * every entry in the journal at path, in sequence order
+     */
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Vec<JournalEntry>> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut entries = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            entries.push(serde_json::from_str(&line)?);
+        }
+        Ok(entries)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /**
+     * sequence number of the last entry written, zero for an empty journal
+     */
+    pub fn last_sequence(&self) -> u64 {
+        self.next_sequence - 1
+    }
+}
@@ -0,0 +1,202 @@
+use crate::models::{Account, OrderError};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+/**
+ * a book the ledger keeps balances for, one balance per asset
+ * External: the world outside the exchange, deposits come from it and paid out withdrawals go to it
+ * PendingWithdrawals: withdrawals taken off a user and not paid out yet
+ * Settlement: counterparty of realized PnL, it carries the PnL of positions still open
+ * Funding: counterparty of funding payments
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum LedgerAccount {
+    User(Uuid),
+    External,
+    PendingWithdrawals,
+    FeeRevenue,
+    InsuranceFund,
+    Settlement,
+    Funding,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LedgerReason {
+    Deposit,
+    Withdrawal,
+    WithdrawalRejected,
+    WithdrawalPaid,
+    Transfer,
+    RealizedPnl,
+    Fee,
+    Funding,
+    LiquidationSurplus,
+    InsurancePayout,
+    InsuranceSeed,
+    Deleverage,
+}
+
+/**
+ * what caused a movement
+ */
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LedgerReference {
+    Trade(Uuid),
+    Withdrawal(Uuid),
+    Funding { symbol: String },
+    Liquidation { symbol: String, user_id: Uuid },
+    Deleverage { symbol: String, bankrupt_user_id: Uuid },
+}
+
+/**
+ * amount of asset moved out of debit and into credit, amount is always positive
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub id: u64,
+    pub debit: LedgerAccount,
+    pub credit: LedgerAccount,
+    pub asset: String,
+    pub amount: BigDecimal,
+    pub reason: LedgerReason,
+    pub reference: Option<LedgerReference>,
+    pub at: DateTime<Utc>,
+}
+
+/**
+ * a user's balance per asset as the ledger has it, only a ledger posting moves it
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Balances(HashMap<String, BigDecimal>);
+
+impl Balances {
+    pub fn get(&self, asset: &str) -> BigDecimal {
+        self.0.get(asset).cloned().unwrap_or(BigDecimal::from(0))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &BigDecimal)> {
+        self.0.iter()
+    }
+
+    fn add(&mut self, asset: &str, amount: BigDecimal) {
+        *self.0.entry(asset.to_string()).or_insert(BigDecimal::from(0)) += amount;
+    }
+}
+
+/**
+ * double-entry record of every balance movement, oldest first
+ * an account's balance is what was credited to it less what was debited from it
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Ledger {
+    entries: Vec<LedgerEntry>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * moves amount from debit to credit and applies it to the balances of the users among them
+     * a negative amount moves it the other way, nothing is recorded for a zero amount
+     */
+    #[allow(clippy::too_many_arguments)]
+    pub fn post(
+        &mut self,
+        accounts: &mut HashMap<Uuid, Account>,
+        debit: LedgerAccount,
+        credit: LedgerAccount,
+        asset: String,
+        amount: BigDecimal,
+        reason: LedgerReason,
+        reference: Option<LedgerReference>,
+        at: DateTime<Utc>,
+    ) -> Result<(), OrderError> {
+        for account in [debit, credit] {
+            if let LedgerAccount::User(user_id) = account {
+                if !accounts.contains_key(&user_id) {
+                    return Err(OrderError::OrderNotFound);
+                }
+            }
+        }
+        let zero = BigDecimal::from(0);
+        if amount == zero {
+            return Ok(());
+        }
+        let (debit, credit, amount) = if amount < zero {
+            (credit, debit, -amount)
+        } else {
+            (debit, credit, amount)
+        };
+
+        if let Some(account) = Self::user_account(accounts, debit) {
+            account.balances.add(&asset, -amount.clone());
+        }
+        if let Some(account) = Self::user_account(accounts, credit) {
+            account.balances.add(&asset, amount.clone());
+        }
+        self.entries.push(LedgerEntry {
+            id: self.entries.len() as u64 + 1,
+            debit,
+            credit,
+            asset,
+            amount,
+            reason,
+            reference,
+            at,
+        });
+        Ok(())
+    }
+
+    fn user_account(accounts: &mut HashMap<Uuid, Account>, account: LedgerAccount) -> Option<&mut Account> {
+        match account {
+            LedgerAccount::User(user_id) => accounts.get_mut(&user_id),
+            _ => None,
+        }
+    }
+
+    pub fn entries(&self) -> &[LedgerEntry] {
+        &self.entries
+    }
+
+    /**
+     * entries that moved money in or out of account, oldest first
+     */
+    pub fn entries_for(&self, account: LedgerAccount) -> impl Iterator<Item = &LedgerEntry> {
+        self.entries.iter().filter(move |entry| entry.debit == account || entry.credit == account)
+    }
+
+    pub fn balance(&self, account: LedgerAccount, asset: &str) -> BigDecimal {
+        self.entries_for(account)
+            .filter(|entry| entry.asset == asset)
+            .map(|entry| if entry.credit == account { entry.amount.clone() } else { -entry.amount.clone() })
+            .sum()
+    }
+
+    /**
+     * every account's balance per asset
+     */
+    pub fn balances(&self) -> BTreeMap<(LedgerAccount, String), BigDecimal> {
+        let mut balances = BTreeMap::new();
+        for entry in &self.entries {
+            *balances.entry((entry.credit, entry.asset.clone())).or_insert(BigDecimal::from(0)) += &entry.amount;
+            *balances.entry((entry.debit, entry.asset.clone())).or_insert(BigDecimal::from(0)) -= &entry.amount;
+        }
+        balances
+    }
+
+    /**
+     * sum of all account balances per asset, every one of them is zero while the books balance
+     */
+    pub fn trial_balance(&self) -> HashMap<String, BigDecimal> {
+        let mut totals = HashMap::new();
+        for ((_, asset), balance) in self.balances() {
+            *totals.entry(asset).or_insert(BigDecimal::from(0)) += balance;
+        }
+        totals
+    }
+}
@@ -0,0 +1,14 @@
+pub mod models;
+pub mod orderbook;
+pub mod account;
+pub mod margin;
+pub mod funding;
+pub mod exchange;
+pub mod contract;
+pub mod clock;
+pub mod journal;
+pub mod snapshot;
+pub mod fees;
+pub mod insurance;
+pub mod ledger;
+pub mod withdrawal;
@@ -1,10 +1,3 @@
-mod models;
-mod orderbook;
-mod account;
-mod margin;
-mod funding;
-mod exchange;
-
 fn main() {
     println!("Hello, world!");
 }
@@ -1,7 +1,12 @@
-use crate::models::{MarginType, Side, PositionType};
+use crate::models::{MarginType, Side};
 use bigdecimal::BigDecimal;
 use std::str::FromStr;
 
+/**
+ * share of a position's notional that has to stay covered before it is liquidated
+ */
+const MAINTENANCE_MARGIN_RATE: &str = "0.005"; // 0.5%
+
 pub struct MarginCalculator;
 
 impl MarginCalculator {
@@ -25,7 +30,7 @@ impl MarginCalculator {
         leverage: &BigDecimal,
         margin_type: MarginType,
     ) -> BigDecimal {
-        let maintenance_margin = BigDecimal::from_str("0.005").unwrap(); // 0.5%
+        let maintenance_margin = BigDecimal::from_str(MAINTENANCE_MARGIN_RATE).unwrap();
         let buffer = match margin_type {
             MarginType::Isolated => BigDecimal::from_str("0.001").unwrap(), // 0.1% buffer
             MarginType::Cross => BigDecimal::from_str("0.002").unwrap(),    // 0.2% buffer
@@ -47,6 +52,29 @@ impl MarginCalculator {
         }
     }
 
+    /**
+     * margin that has to stay covered for a position of this notional
+     */
+    pub fn calculate_maintenance_margin(notional: &BigDecimal) -> BigDecimal {
+        notional * BigDecimal::from_str(MAINTENANCE_MARGIN_RATE).unwrap()
+    }
+
+    /**
+     * price at which a position has lost its whole initial margin
+     * a liquidation filling beyond it leaves a deficit someone else has to cover
+     */
+    pub fn calculate_bankruptcy_price(
+        entry_price: &BigDecimal,
+        side: Side,
+        leverage: &BigDecimal,
+    ) -> BigDecimal {
+        let margin_fraction = BigDecimal::from(1) / leverage;
+        match side {
+            Side::Buy => entry_price * (BigDecimal::from(1) - margin_fraction),
+            Side::Sell => entry_price * (BigDecimal::from(1) + margin_fraction),
+        }
+    }
+
     pub fn is_position_liquidated(
         current_price: &BigDecimal,
         entry_price: &BigDecimal,
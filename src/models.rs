@@ -1,6 +1,9 @@
+use crate::clock::{system_clock, Clock};
+use crate::ledger::Balances;
 use bigdecimal::BigDecimal;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
 use uuid::Uuid;
 use std::str::FromStr;
 use thiserror::Error;
@@ -12,12 +15,25 @@ pub enum Side {
     Sell
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+/**
+ * leg of a hedge mode position
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum PositionSide {
     Long,
     Short
 }
 
+/**
+ * OneWay - one netted position per symbol, a fill on the other side reduces or flips it
+ * Hedge - a long and a short leg per symbol, orders say which leg they open or close
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PositionMode {
+    OneWay,
+    Hedge,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum PositionType {
     Spot,    
@@ -38,20 +54,62 @@ pub enum OrderType {
     StopLimit,
 }
 
+/**
+ * price a stop order watches for its trigger
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TriggerBy {
+    LastPrice,
+    MarkPrice,
+}
+
+/**
+ * what to do with a post-only order that would cross the spread
+ * Reject - refuse the order
+ * Slide - reprice it one tick behind the opposite best price so it rests as a maker
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PostOnly {
+    Reject,
+    Slide,
+}
+
+/**
+ * what happens when an incoming order would match a resting order from the same user
+ * CancelNewest - cancel the rest of the incoming order
+ * CancelOldest - cancel the resting order and keep matching
+ * CancelBoth - cancel both
+ * DecrementAndCancel - shrink both by the smaller open quantity, which cancels the smaller one
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SelfTradePrevention {
+    CancelNewest,
+    CancelOldest,
+    CancelBoth,
+    DecrementAndCancel,
+}
+
 /**
  * GTC (Good Till Cancel) - order stays active until filled or canceled
  * IOC (Immediate Or Cancel) - fills immediately whatever it can, cancels the rest
  * FOK (Fill Or Kill) - must fill completely or not at all
+ * GTD (Good Till Date) - like GTC, but cancelled by the expiry sweep once the timestamp passes
  */
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum TimeInForce {
     GTC,
     IOC, 
     FOK,
+    GTD(chrono::DateTime<chrono::Utc>),
 }
 
 /*
 * trading order metadata
+* price is the limit price, trigger_price only applies to Stop and StopLimit orders
+* display_quantity makes a limit order an iceberg that only shows slices of that size
+* sequence is the book sequence of the last change to the order, zero until the book accepts it
+* position_side is the hedge mode leg the order opens or closes, None in one-way mode
 */
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
@@ -61,16 +119,29 @@ pub struct Order {
     pub side: Side,
     pub order_type: OrderType,
     pub price: BigDecimal,
+    pub trigger_price: Option<BigDecimal>,
+    pub trigger_by: TriggerBy,
     pub quantity: BigDecimal,
     pub filled_quantity: BigDecimal,
+    pub display_quantity: Option<BigDecimal>,
     pub leverage: Option<BigDecimal>,
     pub time_in_force: TimeInForce,
+    pub post_only: Option<PostOnly>,
+    pub reduce_only: bool,
+    pub self_trade_prevention: Option<SelfTradePrevention>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
+    #[serde(default)]
+    pub sequence: u64,
+    #[serde(default)]
+    pub position_side: Option<PositionSide>,
 }
 
 /**
  * Record of a transaction
+ * aggressor_side is the side of the incoming (taker) order, the other side made the price
+ * fees are in fee_asset, negative for a rebate; the book leaves them at zero with no asset
+ * and the exchange fills them in when it settles the trade
  */
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Trade {
@@ -78,13 +149,101 @@ pub struct Trade {
     pub symbol: String,
     pub buyer_order_id: Uuid,
     pub seller_order_id: Uuid,
+    pub buyer_user_id: Uuid,
+    pub seller_user_id: Uuid,
+    pub buyer_leverage: Option<BigDecimal>,
+    pub seller_leverage: Option<BigDecimal>,
+    pub buyer_position_side: Option<PositionSide>,
+    pub seller_position_side: Option<PositionSide>,
     pub price: BigDecimal,
     pub quantity: BigDecimal,
+    pub aggressor_side: Side,
+    pub buyer_fee: BigDecimal,
+    pub seller_fee: BigDecimal,
+    pub fee_asset: Option<String>,
     pub executed_at: chrono::DateTime<chrono::Utc>,
+    pub sequence: u64,
+}
+
+/**
+ * a match that self-trade prevention stopped, with how much of each order it cancelled
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelfTradeEvent {
+    pub symbol: String,
+    pub user_id: Uuid,
+    pub taker_order_id: Uuid,
+    pub maker_order_id: Uuid,
+    pub mode: SelfTradePrevention,
+    pub taker_cancelled_quantity: BigDecimal,
+    pub maker_cancelled_quantity: BigDecimal,
+    pub prevented_at: chrono::DateTime<chrono::Utc>,
+    pub sequence: u64,
+}
+
+/**
+ * a position the exchange closed because the mark price crossed its liquidation price
+ * bankruptcy_price: where the position's margin is used up
+ * fill_price: average price the liquidation order traded at, None if the book had nothing
+ * deleveraged_quantity: what auto-deleveraging closed at the bankruptcy price after the book
+ * remaining_quantity: what neither could close, still open on the position
+ * surplus: margin left over from filling better than the bankruptcy price, paid into the insurance fund
+ * deficit: loss beyond the position's margin, from filling worse than the bankruptcy price
+ * insurance_payout: the part of the deficit the insurance fund covered, the trader carries the rest
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiquidationEvent {
+    pub symbol: String,
+    pub user_id: Uuid,
+    pub order_id: Uuid,
+    pub side: Side,
+    pub position_side: Option<PositionSide>,
+    pub quantity: BigDecimal,
+    pub mark_price: BigDecimal,
+    pub bankruptcy_price: BigDecimal,
+    pub fill_price: Option<BigDecimal>,
+    pub deleveraged_quantity: BigDecimal,
+    pub remaining_quantity: BigDecimal,
+    pub surplus: BigDecimal,
+    pub deficit: BigDecimal,
+    pub insurance_payout: BigDecimal,
+    pub liquidated_at: chrono::DateTime<chrono::Utc>,
+}
+
+/**
+ * a profitable position cut down to close out a bankrupt one the book and insurance fund could not
+ * side is the side of the deleveraged position, closed by quantity at the bankrupt position's bankruptcy price
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdlEvent {
+    pub symbol: String,
+    pub user_id: Uuid,
+    pub bankrupt_user_id: Uuid,
+    pub side: Side,
+    pub position_side: Option<PositionSide>,
+    pub quantity: BigDecimal,
+    pub price: BigDecimal,
+    pub realized_pnl: BigDecimal,
+    pub deleveraged_at: chrono::DateTime<chrono::Utc>,
+}
+
+/**
+ * everything that came out of matching one order
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MatchResult {
+    pub trades: Vec<Trade>,
+    pub cancelled: Vec<Order>, // resting orders the walk cancelled, each stamped with the cancel's sequence
+    pub self_trades: Vec<SelfTradeEvent>,
+    pub liquidations: Vec<LiquidationEvent>,
+    pub deleverages: Vec<AdlEvent>,
 }
 
 /**
  * open market position for a user
+ * realized_pnl, fees and funding add up over the life of the position, fees are what it paid
+ * (negative for net rebates) and funding what it received (negative when it paid)
+ * unrealized_pnl is against the last mark price, zero once the position is flat
  */
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Position {
@@ -98,29 +257,101 @@ pub struct Position {
     pub liquidation_price: Option<BigDecimal>,
     pub margin: Option<BigDecimal>,    
     pub margin_type: Option<MarginType>, 
+    pub position_side: Option<PositionSide>,
+    pub realized_pnl: BigDecimal,
+    pub unrealized_pnl: BigDecimal,
+    pub fees: BigDecimal,
+    pub funding: BigDecimal,
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
 
+/**
+ * PnL of an account's positions added up
+ * net is realized plus unrealized plus funding, less fees
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PnlSummary {
+    pub realized_pnl: BigDecimal,
+    pub unrealized_pnl: BigDecimal,
+    pub fees: BigDecimal,
+    pub funding: BigDecimal,
+    pub net: BigDecimal,
+}
+
+/**
+ * margin set aside for the unfilled part of an open order
+ * quantity: unfilled quantity the hold covers, amount shrinks with it pro rata
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderHold {
+    pub symbol: String,
+    pub asset: String,
+    pub quantity: BigDecimal,
+    pub amount: BigDecimal,
+}
+
 /**
  * manage user balances and positions
  */
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Account {
     pub user_id: Uuid,
-    pub balances: HashMap<String, BigDecimal>, // asset -> balance
-    pub positions: HashMap<String, Position>,  // token -> position
+    pub balances: Balances, // asset -> balance, moved only by ledger postings
+    pub positions: HashMap<String, Position>,  // position key -> position, see Account::position_key
+    pub margin_types: HashMap<String, MarginType>, // token -> margin type, isolated when unset
+    pub position_mode: PositionMode,
+    pub order_holds: HashMap<Uuid, OrderHold>, // order id -> margin held for it
 }
 
 /**
- * order book for a token
- * bids: sorted highest to lowest price
- * asks: sorted lowest to highest price
+ * resting orders at a single price
+ * queue: order ids oldest first, cancelled ids are left in place and skipped when matching
+ * open_quantity: unfilled quantity of the live orders at this price, hidden iceberg quantity included
+ * visible_quantity: the part of open_quantity shown in depth
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PriceLevel {
+    pub queue: VecDeque<Uuid>,
+    pub open_quantity: BigDecimal,
+    pub visible_quantity: BigDecimal,
+    pub order_count: usize,
+}
+
+/**
+ * (price, open quantity) pairs, one per resting order, best price first
+ */
+pub type DepthLevels = Vec<(BigDecimal, BigDecimal)>;
+
+/**
+ * (user, leg) -> how much of that position the user's resting reduce-only orders may still close
+ * fills of any of the user's orders on that side use it up, users not in it are not limited
+ */
+pub type ReduceOnlyLimits = HashMap<(Uuid, Option<PositionSide>), BigDecimal>;
+
+/**
+ * order book for a token, kept as price levels
+ * bids: best bid is the highest key
+ * asks: best ask is the lowest key
+ * orders: every live resting order by id, the levels only hold ids
+ * stop_orders: untriggered Stop and StopLimit orders, kept off the book until they fire
+ * tick_size: minimum price increment, used when sliding post-only orders
+ * expiries: GTD orders by expiry time, ids of orders gone in the meantime are skipped
+ * sequence: last sequence number handed out on this book, every accepted order, trade,
+ *   self-trade prevention, amendment and cancel takes the next one, so a gap means a missed event
+ * clock: stamps trades and events, not part of the serialized book
  */
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OrderBook {
     pub symbol: String,
-    pub bids: Vec<Order>, 
-    pub asks: Vec<Order>, 
+    pub bids: BTreeMap<BigDecimal, PriceLevel>,
+    pub asks: BTreeMap<BigDecimal, PriceLevel>,
+    pub orders: HashMap<Uuid, Order>,
+    pub stop_orders: HashMap<Uuid, Order>,
+    pub tick_size: BigDecimal,
+    pub expiries: BTreeMap<chrono::DateTime<chrono::Utc>, Vec<Uuid>>,
+    pub sequence: u64,
+    #[serde(skip, default = "system_clock")]
+    pub clock: Arc<dyn Clock>,
 }
 
 /**
@@ -143,12 +374,113 @@ pub enum OrderError {
     InsufficientBalance,
     #[error("Invalid order parameters")]
     InvalidOrder,
+    #[error("Unknown symbol")]
+    UnknownSymbol,
+    #[error("Market data is stale")]
+    StaleMarketData,
+    #[error("Price is not a multiple of the tick size")]
+    PriceNotOnTick,
+    #[error("Price has more decimal places than the contract allows")]
+    PriceTooPrecise,
+    #[error("Quantity is not a multiple of the lot size")]
+    QuantityNotOnLot,
+    #[error("Quantity is below the minimum")]
+    QuantityBelowMinimum,
+    #[error("Quantity is above the maximum")]
+    QuantityAboveMaximum,
+    #[error("Notional value is below the minimum")]
+    NotionalBelowMinimum,
+    #[error("Leverage is above the maximum")]
+    LeverageAboveMaximum,
+    #[error("Post-only order would take liquidity")]
+    PostOnlyWouldCross,
+    #[error("Reduce-only order would increase the position")]
+    ReduceOnlyWouldIncrease,
+    #[error("Order expiry is already in the past")]
+    AlreadyExpired,
     #[error("Order not found")]
     OrderNotFound,
     #[error("Position would be liquidated")]
     WouldLiquidate,
     #[error("Funding payment failed")]
     FundingError,
+    #[error("Ledger does not balance: {0}")]
+    LedgerMismatch(String),
+    #[error("Withdrawal not found")]
+    WithdrawalNotFound,
+    #[error("Withdrawal cannot move to that status")]
+    InvalidWithdrawalTransition,
+    #[error("Margin type cannot change while a position or order is open")]
+    MarginTypeLocked,
+    #[error("Position mode cannot change while a position or order is open")]
+    PositionModeLocked,
+    #[error("Order position side does not fit the account's position mode")]
+    InvalidPositionSide,
+    #[error("Fee schedule needs a tier starting at zero volume")]
+    InvalidFeeSchedule,
+    #[error("Journal error: {0}")]
+    JournalError(String),
+    #[error("Snapshot error: {0}")]
+    SnapshotError(String),
+}
+
+impl Side {
+    pub fn opposite(&self) -> Side {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+}
+
+impl Order {
+    pub fn remaining_quantity(&self) -> BigDecimal {
+        self.quantity.clone() - self.filled_quantity.clone()
+    }
+
+    /**
+     * open quantity shown on the book
+     * an iceberg shows its current slice, slices are cut at every display_quantity filled
+     */
+    pub fn visible_quantity(&self) -> BigDecimal {
+        match &self.display_quantity {
+            Some(display) if display > &BigDecimal::from(0) => {
+                let into_slice = &self.filled_quantity % display;
+                (display - into_slice).min(self.remaining_quantity())
+            }
+            _ => self.remaining_quantity(),
+        }
+    }
+
+    pub fn is_stop(&self) -> bool {
+        matches!(self.order_type, OrderType::Stop | OrderType::StopLimit)
+    }
+}
+
+impl TimeInForce {
+    /**
+     * whether an unfilled limit remainder stays on the book
+     */
+    pub fn rests(&self) -> bool {
+        matches!(self, TimeInForce::GTC | TimeInForce::GTD(_))
+    }
+
+    pub fn expires_at(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        match self {
+            TimeInForce::GTD(expiry) => Some(*expiry),
+            _ => None,
+        }
+    }
+}
+
+impl MatchResult {
+    pub fn extend(&mut self, other: MatchResult) {
+        self.trades.extend(other.trades);
+        self.cancelled.extend(other.cancelled);
+        self.self_trades.extend(other.self_trades);
+        self.liquidations.extend(other.liquidations);
+        self.deleverages.extend(other.deleverages);
+    }
 }
 
 // formatterr
@@ -173,7 +505,3 @@ impl FromStr for Side {
         }
     }
 }
-
-
-
-
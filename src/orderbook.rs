@@ -1,163 +1,662 @@
-use crate::models::{Order, OrderBook, Side, Trade, OrderError, OrderType};
+use crate::models::{
+    Order, OrderBook, Side, Trade, OrderError, OrderType, PriceLevel, DepthLevels, TimeInForce, TriggerBy, PostOnly,
+    MatchResult, SelfTradeEvent, SelfTradePrevention, ReduceOnlyLimits,
+};
+use crate::clock::{system_clock, Clock};
 use bigdecimal::BigDecimal;
-use std::cmp::Ordering;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::str::FromStr;
 use uuid::Uuid;
 
+/**
+ * stale ids a level may carry per live order before its queue is compacted
+ */
+const STALE_IDS_PER_ORDER: usize = 2;
+
 impl OrderBook{
     pub fn new (symbol: String) -> Self {
+        Self::with_clock(symbol, system_clock())
+    }
+
+    pub fn with_clock(symbol: String, clock: Arc<dyn Clock>) -> Self {
         OrderBook {
             symbol,
-            bids: Vec::new(),
-            asks: Vec::new(),
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            orders: HashMap::new(),
+            stop_orders: HashMap::new(),
+            tick_size: BigDecimal::from_str("0.01").unwrap(),
+            expiries: BTreeMap::new(),
+            sequence: 0,
+            clock,
         }
     }
-    
-    pub fn add_order(&mut self, order: Order) -> Result<Vec<Trade>, OrderError> {
+
+    pub fn add_order(&mut self, order: Order) -> Result<MatchResult, OrderError> {
+        self.add_order_with_limits(order, &mut ReduceOnlyLimits::new())
+    }
+
+    /**
+     * adds an order, filling resting reduce-only orders no further than limits allow
+     * a resting reduce-only order whose limit is used up is cancelled when the walk reaches it
+     */
+    pub fn add_order_with_limits(&mut self, mut order: Order, limits: &mut ReduceOnlyLimits) -> Result<MatchResult, OrderError> {
+        // a second copy of an id would sit in a queue the index only knows once
+        if self.get_order(order.id).is_some() {
+            return Err(OrderError::InvalidOrder);
+        }
+        if let Some(display) = &order.display_quantity {
+            let can_rest = matches!(order.order_type, OrderType::Limit | OrderType::StopLimit);
+            if !can_rest || display <= &BigDecimal::from(0) {
+                return Err(OrderError::InvalidOrder);
+            }
+        }
+
+        // stops wait in their own pool until trigger_stops fires them
+        if order.is_stop() {
+            if order.trigger_price.is_none() {
+                return Err(OrderError::InvalidOrder);
+            }
+            order.sequence = self.next_sequence();
+            self.track_expiry(&order);
+            self.stop_orders.insert(order.id, order);
+            return Ok(MatchResult::default());
+        }
+
+        if let Some(policy) = order.post_only {
+            self.apply_post_only(&mut order, policy)?;
+        }
+
+        // FOK is all or nothing, so nothing may trade unless the whole quantity can
+        if order.time_in_force == TimeInForce::FOK
+            && self.fillable_quantity(&order, limits) < order.remaining_quantity() {
+            return Ok(MatchResult::default());
+        }
+
+        order.sequence = self.next_sequence();
+
         match order.side {
-            Side::Buy => self.match_buy_order(order),
-            Side::Sell => self.match_sell_order(order),
+            Side::Buy => self.match_buy_order(order, limits),
+            Side::Sell => self.match_sell_order(order, limits),
         }
     }
-        
-    fn match_buy_order(&mut self, mut order: Order) -> Result<Vec<Trade>, OrderError> {
-        let mut trades = Vec::new();
-        let mut remaining_quantity = order.quantity.clone();
 
-        // if even the lowest ask is higher than the price, we cant match ofcc
-        for ask in self.asks.iter_mut() {
-            if order.order_type == OrderType::Limit && ask.price > order.price {
+    fn match_buy_order(&mut self, mut order: Order, limits: &mut ReduceOnlyLimits) -> Result<MatchResult, OrderError> {
+        let mut result = MatchResult::default();
+        let now = self.clock.now();
+
+        // walk the asks from the lowest price up, if even the lowest ask is higher than the price we cant match ofcc
+        while order.remaining_quantity() > BigDecimal::from(0) {
+            let mut level = match self.asks.first_entry() {
+                Some(level) => level,
+                None => break,
+            };
+            if order.order_type == OrderType::Limit && level.key() > &order.price {
                 break;
             }
 
-            let fill_quantity = if ask.quantity.clone() - ask.filled_quantity.clone() < remaining_quantity {
-                ask.quantity.clone() - ask.filled_quantity.clone()
-            } else {
-                remaining_quantity.clone()
-            };
+            Self::fill_level(&self.symbol, now, &mut self.sequence, &mut self.orders, &mut order, level.get_mut(), limits, &mut result);
+            if level.get().order_count == 0 {
+                level.remove();
+            }
+        }
 
-            // making the tradee and adding filled order:
-            let trade = Trade {
-                id: Uuid::new_v4(),
-                symbol: self.symbol.clone(),
-                buyer_order_id: order.id,
-                seller_order_id: ask.id,
-                price: ask.price.clone(),
-                quantity: fill_quantity.clone(),
-                executed_at: chrono::Utc::now(),
-            };
+        // quanitiy for the buy order is still greater than 0, then add the order to the book:
+        if order.remaining_quantity() > BigDecimal::from(0)
+            && order.order_type == OrderType::Limit
+            && order.time_in_force.rests() {
+            self.rest_order(order);
+        }
 
-            trades.push(trade);
-            ask.filled_quantity += &fill_quantity;
-            order.filled_quantity += &fill_quantity;
-            remaining_quantity -= fill_quantity;
+        Ok(result)
+    }
+
+    fn match_sell_order(&mut self, mut order: Order, limits: &mut ReduceOnlyLimits) -> Result<MatchResult, OrderError> {
+        let mut result = MatchResult::default();
+        let now = self.clock.now();
 
-            if remaining_quantity <= BigDecimal::from(0) {
+        // walk the bids from the highest price down, if even the highest bid is lower than the ask then bruhh you ngmi brugh:
+        while order.remaining_quantity() > BigDecimal::from(0) {
+            let mut level = match self.bids.last_entry() {
+                Some(level) => level,
+                None => break,
+            };
+            if order.order_type == OrderType::Limit && level.key() < &order.price {
                 break;
             }
+
+            Self::fill_level(&self.symbol, now, &mut self.sequence, &mut self.orders, &mut order, level.get_mut(), limits, &mut result);
+            if level.get().order_count == 0 {
+                level.remove();
+            }
         }
 
-        // clearing fully filled asks
-        self.asks.retain(|o| o.filled_quantity < o.quantity);
+        // Add remaining order to book if limit order with remaining quantity
+        if order.remaining_quantity() > BigDecimal::from(0)
+            && order.order_type == OrderType::Limit
+            && order.time_in_force.rests() {
+            self.rest_order(order);
+        }
 
-        // quanitiy for the buy order is still greater than 0, then add the order to the book:
-        if remaining_quantity > BigDecimal::from(0) 
-            && order.order_type == OrderType::Limit {
-            order.quantity = remaining_quantity;
-            self.bids.push(order);
-            self.bids.sort_by(|a, b| b.price.cmp(&a.price));
+        Ok(result)
+    }
+
+    /**
+     * makes sure a post-only order cannot take liquidity
+     * a crossing order is either rejected or slid to one tick behind the opposite best price
+     */
+    fn apply_post_only(&self, order: &mut Order, policy: PostOnly) -> Result<(), OrderError> {
+        if order.order_type != OrderType::Limit {
+            return Err(OrderError::InvalidOrder);
         }
 
-        Ok(trades)
+        let behind_best = match order.side {
+            Side::Buy => self.asks.keys().next()
+                .filter(|best_ask| order.price >= **best_ask)
+                .map(|best_ask| best_ask - &self.tick_size),
+            Side::Sell => self.bids.keys().next_back()
+                .filter(|best_bid| order.price <= **best_bid)
+                .map(|best_bid| best_bid + &self.tick_size),
+        };
 
+        if let Some(price) = behind_best {
+            match policy {
+                PostOnly::Reject => return Err(OrderError::PostOnlyWouldCross),
+                PostOnly::Slide => order.price = price,
+            }
+        }
+        Ok(())
     }
 
-    fn match_sell_order(&mut self, mut order: Order) -> Result<Vec<Trade>, OrderError> {
-        let mut trades = Vec::new();
-        let mut remaining_quantity = order.quantity.clone();
-
-        for bid in self.bids.iter_mut() {
+    /**
+     * dry run of the matching walk: how much of the order the opposite side could fill right now
+     * stops counting once the order's remaining quantity is covered
+     */
+    fn fillable_quantity(&self, order: &Order, limits: &ReduceOnlyLimits) -> BigDecimal {
+        let wanted = order.remaining_quantity();
+        let mut limits = limits.clone();
+        let per_order = order.self_trade_prevention.is_some() || !limits.is_empty();
+        let levels: Box<dyn Iterator<Item = (&BigDecimal, &PriceLevel)>> = match order.side {
+            Side::Buy => Box::new(self.asks.iter()),
+            Side::Sell => Box::new(self.bids.iter().rev()),
+        };
 
-            // if even the highest big price is lower than the ask then bruhh you ngmi brugh:
-            if order.order_type == OrderType::Limit && bid.price < order.price {
+        let mut available = BigDecimal::from(0);
+        for (price, level) in levels {
+            let crosses = match order.side {
+                Side::Buy => price <= &order.price,
+                Side::Sell => price >= &order.price,
+            };
+            if order.order_type == OrderType::Limit && !crosses {
                 break;
             }
 
-            let fill_quantity: BigDecimal = if bid.quantity.clone() - bid.filled_quantity.clone() < remaining_quantity.clone() {
-                bid.quantity.clone() - bid.filled_quantity.clone()
-            } else {
-                remaining_quantity.clone()
+            // a level counts in full unless the walk may cut some of its orders short
+            if !per_order {
+                available += &level.open_quantity;
+                if available >= wanted {
+                    break;
+                }
+                continue;
+            }
+            for resting in level.queue.iter().filter_map(|id| self.orders.get(id)) {
+                // the walk skips the user's own orders only under CancelOldest, every other mode
+                // cuts the incoming order short at the first one
+                if let Some(mode) = order.self_trade_prevention.filter(|_| resting.user_id == order.user_id) {
+                    if mode != SelfTradePrevention::CancelOldest {
+                        return available;
+                    }
+                    continue;
+                }
+
+                // as in fill_level, reduce-only orders fill no further than their limit and every fill
+                // of the same user and leg uses it up
+                let mut quantity = resting.remaining_quantity();
+                if let Some(limit) = limits.get_mut(&(resting.user_id, resting.position_side)) {
+                    if resting.reduce_only {
+                        quantity = quantity.min(limit.clone());
+                    }
+                    *limit = (&*limit - &quantity).max(BigDecimal::from(0));
+                }
+                available += quantity;
+                if available >= wanted {
+                    return available;
+                }
+            }
+        }
+        available
+    }
+
+    /**
+     * fills the incoming order against one price level in time priority
+     * fully filled resting orders are popped off the front of the queue and dropped from the index
+     */
+    #[allow(clippy::too_many_arguments)]
+    fn fill_level(
+        symbol: &str,
+        now: DateTime<Utc>,
+        sequence: &mut u64,
+        orders: &mut HashMap<Uuid, Order>,
+        order: &mut Order,
+        level: &mut PriceLevel,
+        limits: &mut ReduceOnlyLimits,
+        result: &mut MatchResult,
+    ) {
+        while order.remaining_quantity() > BigDecimal::from(0) {
+            let resting_id = match level.queue.front() {
+                Some(resting_id) => *resting_id,
+                None => break,
+            };
+            let resting = match orders.get_mut(&resting_id) {
+                Some(resting) => resting,
+                None => {
+                    // cancelled while queued
+                    level.queue.pop_front();
+                    continue;
+                }
             };
 
-            let trade = Trade {
-                id: Uuid::new_v4(),
-                symbol: self.symbol.clone(),
-                buyer_order_id: bid.id,
-                seller_order_id: order.id,
-                price: bid.price.clone(),
-                quantity: fill_quantity.clone(),
-                executed_at: chrono::Utc::now(),
+            if resting.user_id == order.user_id {
+                if let Some(mode) = order.self_trade_prevention {
+                    *sequence += 1;
+                    result.self_trades.push(Self::prevent_self_trade(symbol, now, *sequence, order, resting, level, mode));
+                    if resting.remaining_quantity() <= BigDecimal::from(0) {
+                        level.queue.pop_front();
+                        level.order_count -= 1;
+                        orders.remove(&resting_id);
+                    }
+                    continue;
+                }
+            }
+
+            // an iceberg only trades its current slice, the next one joins the back of the queue
+            let mut fill_quantity = resting.visible_quantity().min(order.remaining_quantity());
+
+            // earlier fills in this walk may have closed what a reduce-only order was there to close
+            let limit_key = (resting.user_id, resting.position_side);
+            if let Some(limit) = limits.get(&limit_key).filter(|_| resting.reduce_only) {
+                if *limit <= BigDecimal::from(0) {
+                    level.open_quantity -= resting.remaining_quantity();
+                    level.visible_quantity -= resting.visible_quantity();
+                    level.queue.pop_front();
+                    level.order_count -= 1;
+                    *sequence += 1;
+                    if let Some(mut cancelled) = orders.remove(&resting_id) {
+                        cancelled.sequence = *sequence;
+                        result.cancelled.push(cancelled);
+                    }
+                    continue;
+                }
+                fill_quantity = fill_quantity.min(limit.clone());
+            }
+            let (buyer, seller) = match order.side {
+                Side::Buy => (&*order, &*resting),
+                Side::Sell => (&*resting, &*order),
             };
 
-            trades.push(trade);
+            // making the tradee at the resting price:
+            *sequence += 1;
+            result.trades.push(Trade {
+                id: Self::sequenced_id(symbol, "trade", *sequence),
+                symbol: symbol.to_string(),
+                buyer_order_id: buyer.id,
+                seller_order_id: seller.id,
+                buyer_user_id: buyer.user_id,
+                seller_user_id: seller.user_id,
+                buyer_leverage: buyer.leverage.clone(),
+                seller_leverage: seller.leverage.clone(),
+                buyer_position_side: buyer.position_side,
+                seller_position_side: seller.position_side,
+                price: resting.price.clone(),
+                quantity: fill_quantity.clone(),
+                aggressor_side: order.side,
+                buyer_fee: BigDecimal::from(0),
+                seller_fee: BigDecimal::from(0),
+                fee_asset: None,
+                executed_at: now,
+                sequence: *sequence,
+            });
+
+            if let Some(limit) = limits.get_mut(&limit_key) {
+                *limit = (&*limit - &fill_quantity).max(BigDecimal::from(0));
+            }
 
-            bid.filled_quantity += &fill_quantity;
+            let visible_before = resting.visible_quantity();
+            resting.filled_quantity += &fill_quantity;
             order.filled_quantity += &fill_quantity;
-            remaining_quantity -= fill_quantity;
+            resting.sequence = *sequence;
+            order.sequence = *sequence;
+            level.open_quantity -= &fill_quantity;
+            level.visible_quantity += resting.visible_quantity() - visible_before.clone();
 
-            if remaining_quantity <= BigDecimal::from(0) {
-                break;
-            }            
-            
+            if resting.remaining_quantity() <= BigDecimal::from(0) {
+                level.queue.pop_front();
+                level.order_count -= 1;
+                orders.remove(&resting_id);
+            } else if fill_quantity == visible_before {
+                // slice used up, the next one is posted with fresh time priority
+                level.queue.pop_front();
+                level.queue.push_back(resting_id);
+            }
         }
-        self.bids.retain(|o| o.filled_quantity < o.quantity);
+    }
 
-        // Add remaining order to book if limit order with remaining quantity
-        if remaining_quantity > BigDecimal::from(0) 
-            && order.order_type == OrderType::Limit {
-            order.quantity = remaining_quantity;
-            self.asks.push(order);
-            self.asks.sort_by(|a, b| a.price.cmp(&b.price)); // Ascending for asks
+    /**
+     * cancels open quantity instead of letting an order trade against its own user
+     * a cancelled side has its quantity cut back to what already filled
+     */
+    fn prevent_self_trade(
+        symbol: &str,
+        now: DateTime<Utc>,
+        sequence: u64,
+        order: &mut Order,
+        resting: &mut Order,
+        level: &mut PriceLevel,
+        mode: SelfTradePrevention,
+    ) -> SelfTradeEvent {
+        let (taker_cancelled, maker_cancelled) = match mode {
+            SelfTradePrevention::CancelNewest => (order.remaining_quantity(), BigDecimal::from(0)),
+            SelfTradePrevention::CancelOldest => (BigDecimal::from(0), resting.remaining_quantity()),
+            SelfTradePrevention::CancelBoth => (order.remaining_quantity(), resting.remaining_quantity()),
+            SelfTradePrevention::DecrementAndCancel => {
+                let decrement = order.remaining_quantity().min(resting.remaining_quantity());
+                (decrement.clone(), decrement)
+            }
+        };
+
+        let visible_before = resting.visible_quantity();
+        order.quantity -= &taker_cancelled;
+        resting.quantity -= &maker_cancelled;
+        level.open_quantity -= &maker_cancelled;
+        level.visible_quantity += resting.visible_quantity() - visible_before;
+        order.sequence = sequence;
+        resting.sequence = sequence;
+
+        SelfTradeEvent {
+            symbol: symbol.to_string(),
+            user_id: order.user_id,
+            taker_order_id: order.id,
+            maker_order_id: resting.id,
+            mode,
+            taker_cancelled_quantity: taker_cancelled,
+            maker_cancelled_quantity: maker_cancelled,
+            prevented_at: now,
+            sequence,
         }
+    }
 
-        Ok(trades)
+    /**
+     * queues an order at the back of its price level and indexes it by id
+     */
+    fn rest_order(&mut self, order: Order) {
+        let levels = match order.side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        let level = levels.entry(order.price.clone()).or_default();
+        level.queue.push_back(order.id);
+        level.open_quantity += order.remaining_quantity();
+        level.visible_quantity += order.visible_quantity();
+        level.order_count += 1;
+        self.track_expiry(&order);
+        self.orders.insert(order.id, order);
     }
 
-    pub fn cancel_order(&mut self, order_id: Uuid, side: Side) -> Result<(), OrderError> {
-        match side {
-            Side::Buy => {
-                if let Some(pos) = self.bids.iter().position(|o| o.id == order_id) {
-                    self.bids.remove(pos);
-                    Ok(())
-                } else {
-                    Err(OrderError::OrderNotFound)
-                }
-            }
-            Side::Sell => {
-                if let Some(pos) = self.asks.iter().position(|o| o.id == order_id) {
-                    self.asks.remove(pos);
-                    Ok(())
-                } else {
-                    Err(OrderError::OrderNotFound)
-                }
+    fn track_expiry(&mut self, order: &Order) {
+        if let Some(expiry) = order.time_in_force.expires_at() {
+            self.expiries.entry(expiry).or_default().push(order.id);
+        }
+    }
+
+    /**
+     * ids of live GTD orders (resting or untriggered stops) whose expiry is at or before now
+     * they stay on the book, the caller cancels them
+     */
+    pub fn take_expired(&mut self, now: DateTime<Utc>) -> Vec<Uuid> {
+        let mut expired = Vec::new();
+        while let Some(entry) = self.expiries.first_entry() {
+            if *entry.key() > now {
+                break;
             }
+            expired.extend(entry.remove());
         }
+        // an amended order is tracked again on re-entry, so the same id can come up twice
+        let mut seen = HashSet::new();
+        expired.retain(|id| seen.insert(*id) && self.get_order(*id).is_some());
+        expired
     }
 
     /**
-     * top bids and asks
+     * pulls every stop whose watched price has crossed its trigger out of the pool
+     * buy stops fire at or above the trigger, sell stops at or below
+     * fired orders come back as Market (Stop) or Limit (StopLimit) orders, ready to match, oldest first
+     * and in placement order when created at the same instant
+     * a zero price means nothing has printed yet and never fires anything
      */
-    pub fn get_depth(&self, depth: usize) -> (Vec<(BigDecimal, BigDecimal)>, Vec<(BigDecimal, BigDecimal)>) {
-        let bids = self.bids.iter()
-            .take(depth)
-            .map(|o| (o.price.clone(), o.quantity.clone() - o.filled_quantity.clone()))
+    pub fn trigger_stops(&mut self, last_price: &BigDecimal, mark_price: &BigDecimal) -> Vec<Order> {
+        let fired_ids: Vec<Uuid> = self.stop_orders.values()
+            .filter(|o| {
+                let reference = match o.trigger_by {
+                    TriggerBy::LastPrice => last_price,
+                    TriggerBy::MarkPrice => mark_price,
+                };
+                let trigger = match &o.trigger_price {
+                    Some(trigger) => trigger,
+                    None => return false,
+                };
+                *reference != BigDecimal::from(0) && match o.side {
+                    Side::Buy => reference >= trigger,
+                    Side::Sell => reference <= trigger,
+                }
+            })
+            .map(|o| o.id)
             .collect();
 
-        let asks = self.asks.iter()
-            .take(depth)
-            .map(|o| (o.price.clone(), o.quantity.clone() - o.filled_quantity.clone()))
+        let mut fired: Vec<Order> = fired_ids.iter()
+            .filter_map(|id| self.stop_orders.remove(id))
             .collect();
+        fired.sort_by_key(|o| (o.created_at, o.sequence));
+
+        for order in fired.iter_mut() {
+            order.order_type = match order.order_type {
+                OrderType::StopLimit => OrderType::Limit,
+                _ => OrderType::Market,
+            };
+        }
+        fired
+    }
+
+    /**
+     * live order by id, with its filled quantity so far
+     * untriggered stops are included
+     */
+    pub fn get_order(&self, order_id: Uuid) -> Option<&Order> {
+        self.orders.get(&order_id)
+            .or_else(|| self.stop_orders.get(&order_id))
+    }
+
+    /**
+     * lowers an order's total quantity in place, keeping its queue priority
+     * the new quantity must stay above what has already filled
+     */
+    pub fn reduce_order(&mut self, order_id: Uuid, quantity: BigDecimal) -> Result<(), OrderError> {
+        if let Some(order) = self.stop_orders.get_mut(&order_id) {
+            if quantity > order.quantity || quantity <= order.filled_quantity {
+                return Err(OrderError::InvalidOrder);
+            }
+            order.quantity = quantity;
+            self.sequence += 1;
+            order.sequence = self.sequence;
+            return Ok(());
+        }
+
+        let order = self.orders.get_mut(&order_id)
+            .ok_or(OrderError::OrderNotFound)?;
+        if quantity > order.quantity || quantity <= order.filled_quantity {
+            return Err(OrderError::InvalidOrder);
+        }
+
+        let levels = match order.side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        let visible_before = order.visible_quantity();
+        let open_before = order.remaining_quantity();
+        order.quantity = quantity;
+        if let Some(level) = levels.get_mut(&order.price) {
+            level.open_quantity -= open_before - order.remaining_quantity();
+            level.visible_quantity -= visible_before - order.visible_quantity();
+        }
+        self.sequence += 1;
+        order.sequence = self.sequence;
+        Ok(())
+    }
+
+    /**
+     * changes the price and/or total quantity of a live order, keeping its id
+     * a pure quantity decrease keeps queue priority; a price change or quantity increase
+     * takes the order out and submits it again as a fresh arrival, so it can match
+     */
+    pub fn amend_order(
+        &mut self,
+        order_id: Uuid,
+        price: Option<BigDecimal>,
+        quantity: Option<BigDecimal>,
+    ) -> Result<MatchResult, OrderError> {
+        self.amend_order_with_limits(order_id, price, quantity, &mut ReduceOnlyLimits::new())
+    }
+
+    /**
+     * amend_order, with resting reduce-only orders the re-entry may hit held to limits
+     */
+    pub fn amend_order_with_limits(
+        &mut self,
+        order_id: Uuid,
+        price: Option<BigDecimal>,
+        quantity: Option<BigDecimal>,
+        limits: &mut ReduceOnlyLimits,
+    ) -> Result<MatchResult, OrderError> {
+        let current = self.get_order(order_id)
+            .ok_or(OrderError::OrderNotFound)?
+            .clone();
+        let new_price = price.unwrap_or(current.price.clone());
+        let new_quantity = quantity.unwrap_or(current.quantity.clone());
+        if new_quantity <= current.filled_quantity || new_price < BigDecimal::from(0) {
+            return Err(OrderError::InvalidOrder);
+        }
+
+        // untriggered stops have no queue position to lose
+        if let Some(stop) = self.stop_orders.get_mut(&order_id) {
+            stop.price = new_price;
+            stop.quantity = new_quantity;
+            stop.updated_at = self.clock.now();
+            self.sequence += 1;
+            stop.sequence = self.sequence;
+            return Ok(MatchResult::default());
+        }
+
+        if new_price == current.price && new_quantity <= current.quantity {
+            self.reduce_order(order_id, new_quantity)?;
+            return Ok(MatchResult::default());
+        }
+
+        // a re-entry the book would refuse is refused here, while the order still holds its place
+        if let Some(policy) = current.post_only {
+            let mut entry = current.clone();
+            entry.price = new_price.clone();
+            self.apply_post_only(&mut entry, policy)?;
+        }
+
+        // re-entry takes the amendment's sequence, the removal does not take one of its own
+        let mut order = self.take_order(order_id)?;
+        // the id has to leave its old queue now, it could otherwise come back to life there
+        let levels = match order.side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        if let Some(level) = levels.get_mut(&order.price) {
+            level.queue.retain(|id| *id != order_id);
+        }
+
+        order.price = new_price;
+        order.quantity = new_quantity;
+        order.updated_at = self.clock.now();
+        self.add_order_with_limits(order, limits)
+    }
 
+    /**
+     * removes a resting order and hands it back to the caller, stamped with the cancel's sequence
+     * the id stays queued in its level until matching or compaction reaches it
+     */
+    pub fn cancel_order(&mut self, order_id: Uuid) -> Result<Order, OrderError> {
+        let mut order = self.take_order(order_id)?;
+        order.sequence = self.next_sequence();
+        Ok(order)
+    }
+
+    fn take_order(&mut self, order_id: Uuid) -> Result<Order, OrderError> {
+        if let Some(order) = self.stop_orders.remove(&order_id) {
+            return Ok(order);
+        }
+
+        let order = self.orders.remove(&order_id)
+            .ok_or(OrderError::OrderNotFound)?;
+
+        let levels = match order.side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        if let Some(level) = levels.get_mut(&order.price) {
+            level.open_quantity -= order.remaining_quantity();
+            level.visible_quantity -= order.visible_quantity();
+            level.order_count -= 1;
+
+            if level.order_count == 0 {
+                levels.remove(&order.price);
+            } else if level.queue.len() > level.order_count * (STALE_IDS_PER_ORDER + 1) {
+                let orders = &self.orders;
+                level.queue.retain(|id| orders.contains_key(id));
+            }
+        }
+
+        Ok(order)
+    }
+
+    fn next_sequence(&mut self) -> u64 {
+        self.sequence += 1;
+        self.sequence
+    }
+
+    /**
+     * id for something issued on symbol's book at sequence, so replaying the journal issues the same one
+     * scope keeps trades and exchange-made orders at the same sequence apart
+     */
+    pub fn sequenced_id(symbol: &str, scope: &str, sequence: u64) -> Uuid {
+        // FNV-1a, which unlike the std hasher stays the same across builds
+        let hash = scope.bytes().chain([b':']).chain(symbol.bytes())
+            .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3));
+        Uuid::from_u64_pair(hash, sequence)
+    }
+
+    /**
+     * top resting bids and asks, one (price, quantity) pair per order
+     * best price first and queue order within a price, iceberg orders show only their current slice
+     */
+    pub fn get_depth(&self, depth: usize) -> (DepthLevels, DepthLevels) {
+        let bids = self.depth_of(self.bids.values().rev(), depth);
+        let asks = self.depth_of(self.asks.values(), depth);
         (bids, asks)
     }
 
-}
\ No newline at end of file
+    fn depth_of<'a>(&self, levels: impl Iterator<Item = &'a PriceLevel>, depth: usize) -> DepthLevels {
+        levels
+            .flat_map(|level| level.queue.iter())
+            .filter_map(|order_id| self.orders.get(order_id))
+            .map(|order| (order.price.clone(), order.visible_quantity()))
+            .take(depth)
+            .collect()
+    }
+
+}
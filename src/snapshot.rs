@@ -0,0 +1,81 @@
+use crate::models::OrderError;
+use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/**
+ * layout version written into every snapshot
+ * bump it whenever a change to the snapshotted types breaks older snapshots
+ */
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/**
+ * Json: readable, for inspection and debugging
+ * Cbor: compact binary, for fast restore
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SnapshotFormat {
+    Json,
+    Cbor,
+}
+
+/**
+ * versioned envelope around a piece of engine state
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot<T> {
+    pub version: u32,
+    pub taken_at: DateTime<Utc>,
+    pub state: T,
+}
+
+#[derive(Deserialize)]
+struct SnapshotHeader {
+    version: u32,
+}
+
+/**
+ * wraps state in an envelope stamped with the current version and writes it out
+ */
+pub fn encode<T: Serialize>(state: &T, taken_at: DateTime<Utc>, format: SnapshotFormat) -> Result<Vec<u8>, OrderError> {
+    let snapshot = Snapshot {
+        version: SNAPSHOT_VERSION,
+        taken_at,
+        state,
+    };
+    match format {
+        SnapshotFormat::Json => serde_json::to_vec(&snapshot)
+            .map_err(|e| OrderError::SnapshotError(e.to_string())),
+        SnapshotFormat::Cbor => {
+            let mut bytes = Vec::new();
+            ciborium::into_writer(&snapshot, &mut bytes)
+                .map_err(|e| OrderError::SnapshotError(e.to_string()))?;
+            Ok(bytes)
+        }
+    }
+}
+
+/**
+ * reads a snapshot back
+ * the version is checked on its own first, so an incompatible snapshot is reported as such
+ * instead of failing somewhere inside the state
+ */
+pub fn decode<T: DeserializeOwned>(bytes: &[u8], format: SnapshotFormat) -> Result<Snapshot<T>, OrderError> {
+    let header: SnapshotHeader = read(bytes, format)?;
+    if header.version != SNAPSHOT_VERSION {
+        return Err(OrderError::SnapshotError(format!(
+            "unsupported snapshot version {}, expected {}",
+            header.version, SNAPSHOT_VERSION
+        )));
+    }
+    read(bytes, format)
+}
+
+fn read<T: DeserializeOwned>(bytes: &[u8], format: SnapshotFormat) -> Result<T, OrderError> {
+    match format {
+        SnapshotFormat::Json => serde_json::from_slice(bytes)
+            .map_err(|e| OrderError::SnapshotError(e.to_string())),
+        SnapshotFormat::Cbor => ciborium::from_reader(bytes)
+            .map_err(|e| OrderError::SnapshotError(e.to_string())),
+    }
+}
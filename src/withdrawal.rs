@@ -0,0 +1,81 @@
+use crate::models::OrderError;
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/**
+ * Pending: requested, funds already taken off the balance
+ * Approved: cleared to be paid out
+ * Completed: paid out, final
+ * Rejected: funds returned to the balance, final
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WithdrawalStatus {
+    Pending,
+    Approved,
+    Completed,
+    Rejected,
+}
+
+/**
+ * one step in a withdrawal's life, note carries the reason for a rejection
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WithdrawalEvent {
+    pub status: WithdrawalStatus,
+    pub at: DateTime<Utc>,
+    pub note: Option<String>,
+}
+
+/**
+ * a request to take funds out of an account
+ * history holds every status it went through, oldest first
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Withdrawal {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub asset: String,
+    pub amount: BigDecimal,
+    pub status: WithdrawalStatus,
+    pub history: Vec<WithdrawalEvent>,
+}
+
+impl Withdrawal {
+    pub fn new(id: Uuid, user_id: Uuid, asset: String, amount: BigDecimal, at: DateTime<Utc>) -> Self {
+        Withdrawal {
+            id,
+            user_id,
+            asset,
+            amount,
+            status: WithdrawalStatus::Pending,
+            history: vec![WithdrawalEvent {
+                status: WithdrawalStatus::Pending,
+                at,
+                note: None,
+            }],
+        }
+    }
+
+    /**
+     * moves to the next status if the current one allows it
+     * pending can be approved or rejected, approved can be completed or rejected
+     */
+    pub fn transition(&mut self, status: WithdrawalStatus, at: DateTime<Utc>, note: Option<String>) -> Result<(), OrderError> {
+        let allowed = matches!(
+            (self.status, status),
+            (WithdrawalStatus::Pending, WithdrawalStatus::Approved)
+                | (WithdrawalStatus::Pending, WithdrawalStatus::Rejected)
+                | (WithdrawalStatus::Approved, WithdrawalStatus::Completed)
+                | (WithdrawalStatus::Approved, WithdrawalStatus::Rejected)
+        );
+        if !allowed {
+            return Err(OrderError::InvalidWithdrawalTransition);
+        }
+
+        self.status = status;
+        self.history.push(WithdrawalEvent { status, at, note });
+        Ok(())
+    }
+}
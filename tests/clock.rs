@@ -0,0 +1,70 @@
+mod common;
+
+use chrono::{Duration, TimeZone, Utc};
+use common::{clock, funded, mark, mark_on, order, spec_for};
+use order_book::clock::Clock;
+use order_book::exchange::Exchange;
+use order_book::models::{Side, TimeInForce};
+
+#[test]
+fn funding_replays_a_day_on_the_simulated_clock() {
+    let (clock, mut exchange, [long, short]) = funded([1_000, 1_000]);
+    exchange.place_order(order(&exchange, long, Side::Buy, 100, 2, None)).unwrap();
+    exchange.place_order(order(&exchange, short, Side::Sell, 100, 2, None)).unwrap();
+
+    for _ in 0..3 {
+        clock.advance(Duration::hours(8));
+        mark(&mut exchange, 100);
+        exchange.run_funding().unwrap();
+    }
+
+    let history = exchange.funding_calculator.get_funding_history();
+    assert_eq!(history.len(), 3);
+    assert_eq!(history[2].next_funding_time, Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap());
+
+    let payments = exchange.funding_calculator.get_funding_payments();
+    assert_eq!(payments.len(), 6);
+    assert_eq!(payments[5].timestamp, clock.now());
+}
+
+#[test]
+fn orders_are_rejected_once_market_data_goes_stale() {
+    let (clock, mut exchange, [long, _]) = funded([1_000, 1_000]);
+    clock.advance(Duration::seconds(31));
+
+    assert!(exchange.place_order(order(&exchange, long, Side::Buy, 100, 2, None)).is_err());
+}
+
+#[test]
+fn gtd_orders_expire_when_the_clock_passes_their_expiry() {
+    let (clock, mut exchange, [long, _]) = funded([1_000, 1_000]);
+    let expiry = clock.now() + Duration::minutes(5);
+    let mut gtd = order(&exchange, long, Side::Buy, 100, 2, None);
+    gtd.time_in_force = TimeInForce::GTD(expiry);
+    let order_id = gtd.id;
+    exchange.place_order(gtd).unwrap();
+
+    clock.advance(Duration::minutes(4));
+    assert!(exchange.expire_orders().unwrap().is_empty());
+
+    clock.advance(Duration::minutes(1));
+    let expired = exchange.expire_orders().unwrap();
+    assert_eq!(expired[0].id, order_id);
+    assert!(exchange.get_order("BTC-PERP", order_id).is_none());
+}
+
+#[test]
+fn market_data_updates_are_numbered_per_symbol() {
+    let clock = clock();
+    let specs = vec![spec_for("BTC-PERP"), spec_for("ETH-PERP")];
+    let mut exchange = Exchange::with_clock(specs, Duration::hours(8), "USDT".to_string(), clock.clone());
+    assert_eq!(exchange.get_market_data("BTC-PERP").unwrap().sequence, 0);
+
+    mark_on(&mut exchange, "BTC-PERP", 100);
+    clock.advance(Duration::seconds(1));
+    mark_on(&mut exchange, "BTC-PERP", 101);
+    mark_on(&mut exchange, "ETH-PERP", 100);
+
+    assert_eq!(exchange.get_market_data("BTC-PERP").unwrap().sequence, 2);
+    assert_eq!(exchange.get_market_data("ETH-PERP").unwrap().sequence, 1);
+}
@@ -0,0 +1,132 @@
+#![allow(dead_code)]
+
+use bigdecimal::BigDecimal;
+use chrono::{Duration, TimeZone, Utc};
+use order_book::clock::SimulatedClock;
+use order_book::contract::ContractSpec;
+use order_book::exchange::Exchange;
+use order_book::models::{MatchResult, Order, OrderType, Side, TimeInForce, TriggerBy};
+use std::str::FromStr;
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub fn spec() -> ContractSpec {
+    spec_for("BTC-PERP")
+}
+
+/**
+ * whole-unit ticks and lots, 1 to 1,000 contracts, up to 100x, settled in USDT
+ */
+pub fn spec_for(symbol: &str) -> ContractSpec {
+    ContractSpec {
+        symbol: symbol.to_string(),
+        base_asset: symbol.trim_end_matches("-PERP").to_string(),
+        quote_asset: "USDT".to_string(),
+        tick_size: BigDecimal::from(1),
+        lot_size: BigDecimal::from(1),
+        min_quantity: BigDecimal::from(1),
+        max_quantity: BigDecimal::from(1_000),
+        min_notional: BigDecimal::from(0),
+        max_leverage: BigDecimal::from(100),
+        price_precision: 0,
+    }
+}
+
+pub fn decimal(value: &str) -> BigDecimal {
+    BigDecimal::from_str(value).unwrap()
+}
+
+/**
+ * simulated clock starting at midnight on 2024-01-01
+ */
+pub fn clock() -> Arc<SimulatedClock> {
+    Arc::new(SimulatedClock::new(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()))
+}
+
+/**
+ * BTC-PERP settled in USDT with 8 hourly funding, running on clock
+ */
+pub fn exchange(clock: &Arc<SimulatedClock>) -> Exchange {
+    Exchange::with_clock(vec![spec()], Duration::hours(8), "USDT".to_string(), clock.clone())
+}
+
+/**
+ * BTC-PERP marked at 100, with a fresh account for each deposit holding that much USDT
+ */
+pub fn funded<const N: usize>(deposits: [i64; N]) -> (Arc<SimulatedClock>, Exchange, [Uuid; N]) {
+    funded_on(&["BTC-PERP"], deposits)
+}
+
+pub fn funded_on<const N: usize>(symbols: &[&str], deposits: [i64; N]) -> (Arc<SimulatedClock>, Exchange, [Uuid; N]) {
+    let clock = clock();
+    let specs = symbols.iter().map(|symbol| spec_for(symbol)).collect();
+    let mut exchange = Exchange::with_clock(specs, Duration::hours(8), "USDT".to_string(), clock.clone());
+    let users = deposits.map(|amount| {
+        let user_id = Uuid::new_v4();
+        exchange.create_account(user_id).unwrap();
+        exchange.deposit(user_id, "USDT".to_string(), BigDecimal::from(amount)).unwrap();
+        user_id
+    });
+    for symbol in symbols {
+        mark_on(&mut exchange, symbol, 100);
+    }
+    (clock, exchange, users)
+}
+
+/**
+ * GTC limit order on BTC-PERP
+ */
+pub fn order(exchange: &Exchange, user_id: Uuid, side: Side, price: i64, quantity: i64, leverage: Option<i64>) -> Order {
+    order_on(exchange, user_id, "BTC-PERP", side, price, quantity, leverage)
+}
+
+pub fn order_on(
+    exchange: &Exchange,
+    user_id: Uuid,
+    symbol: &str,
+    side: Side,
+    price: i64,
+    quantity: i64,
+    leverage: Option<i64>,
+) -> Order {
+    let now = exchange.clock.now();
+    Order {
+        id: Uuid::new_v4(),
+        user_id,
+        symbol: symbol.to_string(),
+        side,
+        order_type: OrderType::Limit,
+        price: BigDecimal::from(price),
+        trigger_price: None,
+        trigger_by: TriggerBy::LastPrice,
+        quantity: BigDecimal::from(quantity),
+        filled_quantity: BigDecimal::from(0),
+        display_quantity: None,
+        leverage: leverage.map(BigDecimal::from),
+        time_in_force: TimeInForce::GTC,
+        post_only: None,
+        reduce_only: false,
+        self_trade_prevention: None,
+        created_at: now,
+        updated_at: now,
+        sequence: 0,
+        position_side: None,
+    }
+}
+
+/**
+ * fresh BTC-PERP market data with mark and index both at price and no open interest
+ */
+pub fn mark(exchange: &mut Exchange, price: i64) -> MatchResult {
+    mark_on(exchange, "BTC-PERP", price)
+}
+
+pub fn mark_on(exchange: &mut Exchange, symbol: &str, price: i64) -> MatchResult {
+    exchange.update_market_data(
+        symbol,
+        BigDecimal::from(price),
+        BigDecimal::from(price),
+        BigDecimal::from(0),
+        BigDecimal::from(0),
+    ).unwrap()
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use bigdecimal::BigDecimal;
+use common::decimal;
+use order_book::contract::ContractSpec;
+use order_book::models::{Order, OrderError, OrderType, Side, TimeInForce, TriggerBy};
+use uuid::Uuid;
+
+/**
+ * 0.5 ticks, 0.1 lots, 0.5 to 100 contracts, at least 10 notional, up to 50x
+ */
+fn spec() -> ContractSpec {
+    ContractSpec {
+        symbol: "BTC-PERP".to_string(),
+        base_asset: "BTC".to_string(),
+        quote_asset: "USDT".to_string(),
+        tick_size: decimal("0.5"),
+        lot_size: decimal("0.1"),
+        min_quantity: decimal("0.5"),
+        max_quantity: BigDecimal::from(100),
+        min_notional: BigDecimal::from(10),
+        max_leverage: BigDecimal::from(50),
+        price_precision: 1,
+    }
+}
+
+fn order(price: &str, quantity: &str) -> Order {
+    let now = chrono::Utc::now();
+    Order {
+        id: Uuid::new_v4(),
+        user_id: Uuid::new_v4(),
+        symbol: "BTC-PERP".to_string(),
+        side: Side::Buy,
+        order_type: OrderType::Limit,
+        price: decimal(price),
+        trigger_price: None,
+        trigger_by: TriggerBy::LastPrice,
+        quantity: decimal(quantity),
+        filled_quantity: BigDecimal::from(0),
+        display_quantity: None,
+        leverage: Some(BigDecimal::from(10)),
+        time_in_force: TimeInForce::GTC,
+        post_only: None,
+        reduce_only: false,
+        self_trade_prevention: None,
+        created_at: now,
+        updated_at: now,
+        sequence: 0,
+        position_side: None,
+    }
+}
+
+fn validate(order: &Order) -> Result<(), OrderError> {
+    spec().validate_order(order, &BigDecimal::from(100))
+}
+
+#[test]
+fn orders_within_the_rules_pass() {
+    assert!(validate(&order("100.5", "0.5")).is_ok());
+    assert!(validate(&order("100", "100")).is_ok());
+}
+
+#[test]
+fn price_off_the_tick_is_rejected() {
+    assert!(matches!(validate(&order("100.2", "1")), Err(OrderError::PriceNotOnTick)));
+
+    let mut stop = order("100", "1");
+    stop.order_type = OrderType::Stop;
+    stop.trigger_price = Some(decimal("99.9"));
+    assert!(matches!(validate(&stop), Err(OrderError::PriceNotOnTick)));
+}
+
+#[test]
+fn price_past_the_precision_is_rejected() {
+    let mut spec = spec();
+    spec.tick_size = decimal("0.25");
+    assert!(spec.validate_order(&order("100.5", "1"), &BigDecimal::from(100)).is_ok());
+    assert!(matches!(spec.validate_order(&order("100.25", "1"), &BigDecimal::from(100)), Err(OrderError::PriceTooPrecise)));
+}
+
+#[test]
+fn quantity_off_the_lot_is_rejected() {
+    assert!(matches!(validate(&order("100", "1.05")), Err(OrderError::QuantityNotOnLot)));
+
+    let mut iceberg = order("100", "1");
+    iceberg.display_quantity = Some(decimal("0.25"));
+    assert!(matches!(validate(&iceberg), Err(OrderError::QuantityNotOnLot)));
+}
+
+#[test]
+fn quantity_below_the_minimum_is_rejected() {
+    assert!(matches!(validate(&order("100", "0.4")), Err(OrderError::QuantityBelowMinimum)));
+}
+
+#[test]
+fn quantity_above_the_maximum_is_rejected() {
+    assert!(matches!(validate(&order("100", "100.1")), Err(OrderError::QuantityAboveMaximum)));
+}
+
+#[test]
+fn notional_below_the_minimum_is_rejected() {
+    assert!(matches!(validate(&order("19.5", "0.5")), Err(OrderError::NotionalBelowMinimum)));
+
+    // market orders are taken at the reference price
+    let mut market = order("1000", "0.5");
+    market.order_type = OrderType::Market;
+    assert!(matches!(spec().validate_order(&market, &BigDecimal::from(19)), Err(OrderError::NotionalBelowMinimum)));
+}
+
+#[test]
+fn leverage_that_is_not_positive_or_above_the_maximum_is_rejected() {
+    let mut order = order("100", "1");
+    order.leverage = Some(BigDecimal::from(51));
+    assert!(matches!(validate(&order), Err(OrderError::LeverageAboveMaximum)));
+    order.leverage = Some(BigDecimal::from(0));
+    assert!(matches!(validate(&order), Err(OrderError::InvalidOrder)));
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use bigdecimal::BigDecimal;
+use common::{funded_on, mark_on, order_on};
+use order_book::exchange::Exchange;
+use order_book::models::{MarginType, MatchResult, OrderError, Side};
+use uuid::Uuid;
+
+/**
+ * trader has 100 USDT on cross, maker has deep pockets and no leverage
+ */
+fn setup() -> (Exchange, Uuid, Uuid) {
+    let (_, mut exchange, [trader, maker]) = funded_on(&["BTC-PERP", "ETH-PERP"], [100, 100_000]);
+    for symbol in ["BTC-PERP", "ETH-PERP"] {
+        exchange.set_margin_type(trader, symbol.to_string(), MarginType::Cross).unwrap();
+    }
+    (exchange, trader, maker)
+}
+
+/**
+ * trader goes long 10 @ 100 on 50x, 20 of margin
+ */
+fn open_long(exchange: &mut Exchange, trader: Uuid, maker: Uuid, symbol: &str) -> Result<MatchResult, OrderError> {
+    exchange.place_order(order_on(exchange, maker, symbol, Side::Sell, 100, 10, None)).unwrap();
+    exchange.place_order(order_on(exchange, trader, symbol, Side::Buy, 100, 10, Some(50)))
+}
+
+#[test]
+fn cross_positions_draw_on_one_shared_balance() {
+    let (mut exchange, trader, maker) = setup();
+    exchange.place_order(order_on(&exchange, maker, "BTC-PERP", Side::Sell, 100, 40, None)).unwrap();
+    exchange.place_order(order_on(&exchange, trader, "BTC-PERP", Side::Buy, 100, 40, Some(50))).unwrap();
+
+    // 80 of the 100 is margin for the BTC position, the ETH order needs 22
+    let marks = exchange.mark_prices();
+    let account = exchange.get_account(trader).unwrap();
+    assert_eq!(account.cross_available("USDT", &marks), BigDecimal::from(20));
+    assert!(matches!(open_long(&mut exchange, trader, maker, "ETH-PERP"), Err(OrderError::InsufficientBalance)));
+}
+
+#[test]
+fn equity_and_margin_ratio_follow_unrealized_pnl() {
+    let (mut exchange, trader, maker) = setup();
+    open_long(&mut exchange, trader, maker, "BTC-PERP").unwrap();
+    mark_on(&mut exchange, "BTC-PERP", 104);
+
+    let marks = exchange.mark_prices();
+    let account = exchange.get_account(trader).unwrap();
+    assert_eq!(account.cross_equity("USDT", &marks), BigDecimal::from(140));
+    assert_eq!(account.cross_maintenance_margin(&marks), "5.2".parse::<BigDecimal>().unwrap());
+    assert!(account.positions["BTC-PERP"].liquidation_price.is_none());
+}
+
+#[test]
+fn losses_on_one_symbol_liquidate_every_cross_position() {
+    let (mut exchange, trader, maker) = setup();
+    open_long(&mut exchange, trader, maker, "BTC-PERP").unwrap();
+    open_long(&mut exchange, trader, maker, "ETH-PERP").unwrap();
+    exchange.place_order(order_on(&exchange, maker, "BTC-PERP", Side::Buy, 100, 10, None)).unwrap();
+    exchange.place_order(order_on(&exchange, maker, "ETH-PERP", Side::Buy, 90, 10, None)).unwrap();
+
+    // equity 10 against 9.55 of maintenance
+    assert!(mark_on(&mut exchange, "ETH-PERP", 91).liquidations.is_empty());
+
+    // equity 0: the BTC position goes too, though its own price never moved
+    let result = mark_on(&mut exchange, "ETH-PERP", 90);
+    let mut liquidated: Vec<&str> = result.liquidations.iter().map(|event| event.symbol.as_str()).collect();
+    liquidated.sort();
+    assert_eq!(liquidated, vec!["BTC-PERP", "ETH-PERP"]);
+    assert!(result.liquidations.iter().all(|event| event.deficit == BigDecimal::from(0)));
+
+    let account = exchange.get_account(trader).unwrap();
+    assert!(account.positions.values().all(|position| position.quantity == BigDecimal::from(0)));
+    assert_eq!(account.get_balance("USDT"), BigDecimal::from(0));
+}
+
+#[test]
+fn margin_type_is_locked_while_a_position_is_open() {
+    let (mut exchange, trader, maker) = setup();
+    open_long(&mut exchange, trader, maker, "BTC-PERP").unwrap();
+
+    let switched = exchange.set_margin_type(trader, "BTC-PERP".to_string(), MarginType::Isolated);
+    assert!(matches!(switched, Err(OrderError::MarginTypeLocked)));
+    exchange.set_margin_type(trader, "ETH-PERP".to_string(), MarginType::Isolated).unwrap();
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use bigdecimal::BigDecimal;
+use chrono::Duration;
+use common::{decimal, funded, mark};
+use order_book::clock::{Clock, SimulatedClock};
+use order_book::exchange::Exchange;
+use order_book::fees::{FeeSchedule, FeeTier};
+use order_book::models::{Order, Side, TimeInForce, Trade};
+use std::sync::Arc;
+use uuid::Uuid;
+
+fn order(exchange: &Exchange, user_id: Uuid, side: Side, quantity: i64, time_in_force: TimeInForce) -> Order {
+    let mut order = common::order(exchange, user_id, side, 100, quantity, None);
+    order.time_in_force = time_in_force;
+    order
+}
+
+/**
+ * 2 bps maker rebate and 5 bps taker up to 1,000 notional, then free making and 3 bps taking
+ */
+fn setup() -> (Arc<SimulatedClock>, Exchange, Uuid, Uuid) {
+    let (clock, mut exchange, [maker, taker]) = funded([10_000, 10_000]);
+    let schedule = FeeSchedule::new(vec![
+        FeeTier { min_volume: BigDecimal::from(1_000), maker_rate: decimal("0"), taker_rate: decimal("0.0003") },
+        FeeTier { min_volume: BigDecimal::from(0), maker_rate: decimal("-0.0002"), taker_rate: decimal("0.0005") },
+    ]).unwrap();
+    exchange.set_fee_schedule("BTC-PERP".to_string(), schedule).unwrap();
+    (clock, exchange, maker, taker)
+}
+
+fn trade(exchange: &mut Exchange, maker: Uuid, taker: Uuid, quantity: i64) -> Trade {
+    exchange.place_order(order(exchange, maker, Side::Sell, quantity, TimeInForce::GTC)).unwrap();
+    let mut result = exchange.place_order(order(exchange, taker, Side::Buy, quantity, TimeInForce::IOC)).unwrap();
+    result.trades.remove(0)
+}
+
+#[test]
+fn taker_pays_and_maker_is_rebated_in_the_quote_asset() {
+    let (_, mut exchange, maker, taker) = setup();
+    let trade = trade(&mut exchange, maker, taker, 2);
+
+    assert_eq!(trade.aggressor_side, Side::Buy);
+    assert_eq!(trade.fee_asset.as_deref(), Some("USDT"));
+    assert_eq!(trade.buyer_fee, decimal("0.1"));
+    assert_eq!(trade.seller_fee, decimal("-0.04"));
+    assert_eq!(exchange.get_account(taker).unwrap().get_balance("USDT"), decimal("9999.9"));
+    assert_eq!(exchange.get_account(maker).unwrap().get_balance("USDT"), decimal("10000.04"));
+}
+
+#[test]
+fn rolling_volume_moves_users_up_a_tier_and_back_down() {
+    let (clock, mut exchange, maker, taker) = setup();
+    trade(&mut exchange, maker, taker, 10);
+    assert_eq!(exchange.fee_engine.rolling_volume(taker, clock.now()), BigDecimal::from(1_000));
+
+    let upgraded = trade(&mut exchange, maker, taker, 1);
+    assert_eq!(upgraded.buyer_fee, decimal("0.03"));
+    assert_eq!(upgraded.seller_fee, decimal("0"));
+
+    clock.advance(Duration::days(30));
+    mark(&mut exchange, 100);
+    let expired = trade(&mut exchange, maker, taker, 1);
+    assert_eq!(expired.buyer_fee, decimal("0.05"));
+}
+
+#[test]
+fn schedules_need_a_base_tier_and_a_known_symbol() {
+    let (_, mut exchange, _, _) = setup();
+    let no_base = vec![FeeTier { min_volume: BigDecimal::from(10), maker_rate: decimal("0"), taker_rate: decimal("0") }];
+    assert!(FeeSchedule::new(no_base).is_err());
+
+    let flat = FeeSchedule::flat(decimal("0"), decimal("0.001"));
+    assert!(exchange.set_fee_schedule("ETH-PERP".to_string(), flat).is_err());
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use bigdecimal::BigDecimal;
+use chrono::Duration;
+use common::{decimal, funded, mark};
+use order_book::clock::SimulatedClock;
+use order_book::exchange::Exchange;
+use order_book::models::{Account, Order, OrderError, PositionMode, PositionSide, Side};
+use std::sync::Arc;
+use uuid::Uuid;
+
+fn order(
+    exchange: &Exchange,
+    user_id: Uuid,
+    side: Side,
+    price: i64,
+    quantity: i64,
+    leverage: Option<i64>,
+    position_side: Option<PositionSide>,
+) -> Order {
+    let mut order = common::order(exchange, user_id, side, price, quantity, leverage);
+    order.position_side = position_side;
+    order
+}
+
+fn leg(exchange: &mut Exchange, user_id: Uuid, position_side: PositionSide) -> (Side, BigDecimal) {
+    exchange.get_account(user_id).unwrap()
+        .position("BTC-PERP", Some(position_side))
+        .map(|position| (position.side, position.quantity.clone()))
+        .unwrap()
+}
+
+/**
+ * hedger in hedge mode opens a long of 2 and a short of 1 @ 100 on 10x against a one-way maker
+ */
+fn setup() -> (Arc<SimulatedClock>, Exchange, Uuid, Uuid) {
+    let (clock, mut exchange, [hedger, maker]) = funded([1_000, 1_000]);
+    exchange.set_position_mode(hedger, PositionMode::Hedge).unwrap();
+
+    exchange.place_order(order(&exchange, maker, Side::Sell, 100, 2, None, None)).unwrap();
+    exchange.place_order(order(&exchange, hedger, Side::Buy, 100, 2, Some(10), Some(PositionSide::Long))).unwrap();
+    exchange.place_order(order(&exchange, maker, Side::Buy, 100, 1, None, None)).unwrap();
+    exchange.place_order(order(&exchange, hedger, Side::Sell, 100, 1, Some(10), Some(PositionSide::Short))).unwrap();
+    (clock, exchange, hedger, maker)
+}
+
+#[test]
+fn long_and_short_legs_are_held_side_by_side() {
+    let (_, mut exchange, hedger, maker) = setup();
+
+    assert_eq!(leg(&mut exchange, hedger, PositionSide::Long), (Side::Buy, BigDecimal::from(2)));
+    assert_eq!(leg(&mut exchange, hedger, PositionSide::Short), (Side::Sell, BigDecimal::from(1)));
+    // each leg is margined on its own
+    let account = exchange.get_account(hedger).unwrap();
+    let long = account.position("BTC-PERP", Some(PositionSide::Long)).unwrap();
+    let short = account.position("BTC-PERP", Some(PositionSide::Short)).unwrap();
+    assert_eq!(Account::position_margin(long), BigDecimal::from(20));
+    assert_eq!(Account::position_margin(short), BigDecimal::from(10));
+    assert!(long.liquidation_price.as_ref().unwrap() < &BigDecimal::from(100));
+    assert!(short.liquidation_price.as_ref().unwrap() > &BigDecimal::from(100));
+
+    // the one-way maker is netted as before
+    let position = exchange.get_account(maker).unwrap().positions["BTC-PERP"].clone();
+    assert_eq!((position.side, position.quantity), (Side::Sell, BigDecimal::from(1)));
+}
+
+#[test]
+fn closing_orders_only_reduce_their_own_leg() {
+    let (_, mut exchange, hedger, maker) = setup();
+    exchange.place_order(order(&exchange, maker, Side::Buy, 110, 5, None, None)).unwrap();
+    let close = exchange.place_order(order(&exchange, hedger, Side::Sell, 110, 5, Some(10), Some(PositionSide::Long))).unwrap();
+
+    // capped to the long leg instead of flipping into the short one
+    assert_eq!(close.trades.iter().map(|trade| trade.quantity.clone()).sum::<BigDecimal>(), BigDecimal::from(2));
+    assert_eq!(leg(&mut exchange, hedger, PositionSide::Long).1, BigDecimal::from(0));
+    assert_eq!(leg(&mut exchange, hedger, PositionSide::Short), (Side::Sell, BigDecimal::from(1)));
+    let account = exchange.get_account(hedger).unwrap();
+    assert_eq!(account.position("BTC-PERP", Some(PositionSide::Long)).unwrap().realized_pnl, BigDecimal::from(20));
+
+    // nothing left on the long leg to close
+    let result = exchange.place_order(order(&exchange, hedger, Side::Sell, 110, 1, Some(10), Some(PositionSide::Long)));
+    assert!(matches!(result, Err(OrderError::ReduceOnlyWouldIncrease)));
+}
+
+#[test]
+fn funding_is_paid_per_leg() {
+    let (clock, mut exchange, hedger, _) = setup();
+    clock.advance(Duration::hours(8));
+    mark(&mut exchange, 100);
+    exchange.run_funding().unwrap();
+
+    // 1 bp on each leg's notional, the long pays and the short collects
+    let account = exchange.get_account(hedger).unwrap();
+    assert_eq!(account.position("BTC-PERP", Some(PositionSide::Long)).unwrap().funding, decimal("-0.02"));
+    assert_eq!(account.position("BTC-PERP", Some(PositionSide::Short)).unwrap().funding, decimal("0.01"));
+    assert_eq!(account.get_balance("USDT"), decimal("999.99"));
+}
+
+#[test]
+fn liquidation_closes_only_the_underwater_leg() {
+    let (_, mut exchange, hedger, maker) = setup();
+    exchange.seed_insurance_fund("USDT".to_string(), BigDecimal::from(5)).unwrap();
+    exchange.place_order(order(&exchange, maker, Side::Buy, 88, 2, None, None)).unwrap();
+    let result = exchange.update_market_data(
+        "BTC-PERP",
+        BigDecimal::from(90),
+        BigDecimal::from(90),
+        BigDecimal::from(0),
+        BigDecimal::from(0),
+    ).unwrap();
+
+    assert_eq!(result.liquidations.len(), 1);
+    assert_eq!(result.liquidations[0].user_id, hedger);
+    assert_eq!(result.liquidations[0].position_side, Some(PositionSide::Long));
+    assert_eq!(leg(&mut exchange, hedger, PositionSide::Long).1, BigDecimal::from(0));
+    assert_eq!(leg(&mut exchange, hedger, PositionSide::Short), (Side::Sell, BigDecimal::from(1)));
+    exchange.verify_ledger().unwrap();
+}
+
+#[test]
+fn position_mode_is_locked_while_anything_is_open() {
+    let (_, mut exchange, hedger, maker) = setup();
+    assert!(matches!(exchange.set_position_mode(hedger, PositionMode::OneWay), Err(OrderError::PositionModeLocked)));
+
+    let fresh = Uuid::new_v4();
+    exchange.create_account(fresh).unwrap();
+    exchange.deposit(fresh, "USDT".to_string(), BigDecimal::from(1_000)).unwrap();
+    exchange.place_order(order(&exchange, fresh, Side::Buy, 90, 1, Some(10), None)).unwrap();
+    assert!(matches!(exchange.set_position_mode(fresh, PositionMode::Hedge), Err(OrderError::PositionModeLocked)));
+
+    let idle = Uuid::new_v4();
+    exchange.create_account(idle).unwrap();
+    exchange.set_position_mode(idle, PositionMode::Hedge).unwrap();
+    exchange.set_position_mode(idle, PositionMode::OneWay).unwrap();
+    assert!(matches!(exchange.set_position_mode(maker, PositionMode::Hedge), Err(OrderError::PositionModeLocked)));
+}
+
+#[test]
+fn orders_must_name_a_leg_in_hedge_mode_only() {
+    let (_, mut exchange, hedger, maker) = setup();
+    let result = exchange.place_order(order(&exchange, hedger, Side::Buy, 90, 1, Some(10), None));
+    assert!(matches!(result, Err(OrderError::InvalidPositionSide)));
+    let result = exchange.place_order(order(&exchange, maker, Side::Buy, 90, 1, Some(10), Some(PositionSide::Long)));
+    assert!(matches!(result, Err(OrderError::InvalidPositionSide)));
+}
@@ -0,0 +1,189 @@
+mod common;
+
+use bigdecimal::BigDecimal;
+use chrono::Duration;
+use common::{clock, decimal, mark, order, spec};
+use order_book::clock::{Clock, SimulatedClock};
+use order_book::exchange::Exchange;
+use order_book::fees::FeeSchedule;
+use order_book::journal::{Command, Journal};
+use order_book::models::{Side, TimeInForce};
+use order_book::snapshot::{SnapshotFormat, SNAPSHOT_VERSION};
+use std::path::PathBuf;
+use std::sync::Arc;
+use uuid::Uuid;
+
+fn journal_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("{}-{}.journal", name, Uuid::new_v4()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/**
+ * everything replay has to reproduce, in a form that compares regardless of hash map order
+ */
+fn state(exchange: &Exchange) -> serde_json::Value {
+    serde_json::json!({
+        "accounts": exchange.accounts,
+        "order_books": exchange.order_books,
+        "funding_history": format!("{:?}", exchange.funding_calculator.get_funding_history()),
+        "funding_payments": format!("{:?}", exchange.funding_calculator.get_funding_payments()),
+        "ledger": exchange.ledger,
+        "insurance_funds": exchange.insurance_funds,
+        "withdrawals": exchange.withdrawals,
+        "fee_volumes": exchange.fee_engine,
+    })
+}
+
+fn journaled_session(path: &PathBuf) -> (Arc<SimulatedClock>, Exchange) {
+    let clock = clock();
+    let journal = Journal::open(path).unwrap();
+    let mut exchange = Exchange::with_journal(
+        vec![spec()],
+        Duration::hours(8),
+        "USDT".to_string(),
+        clock.clone(),
+        journal,
+    ).unwrap();
+
+    let (maker, taker) = (Uuid::new_v4(), Uuid::new_v4());
+    exchange.create_account(maker).unwrap();
+    exchange.create_account(taker).unwrap();
+    exchange.deposit(maker, "USDT".to_string(), BigDecimal::from(10_000)).unwrap();
+    exchange.deposit(taker, "USDT".to_string(), BigDecimal::from(10_000)).unwrap();
+    mark(&mut exchange, 101);
+
+    let resting = order(&exchange, maker, Side::Sell, 100, 5, None);
+    let resting_id = resting.id;
+    exchange.place_order(resting).unwrap();
+    let deep = order(&exchange, maker, Side::Sell, 105, 3, None);
+    let deep_id = deep.id;
+    exchange.place_order(deep).unwrap();
+    let mut expiring = order(&exchange, taker, Side::Buy, 95, 2, None);
+    expiring.time_in_force = TimeInForce::GTD(clock.now() + Duration::minutes(1));
+    exchange.place_order(expiring).unwrap();
+
+    clock.advance(Duration::seconds(1));
+    let mut sweep = order(&exchange, taker, Side::Buy, 100, 3, None);
+    sweep.time_in_force = TimeInForce::IOC;
+    exchange.place_order(sweep).unwrap();
+    exchange.amend_order(maker, "BTC-PERP".to_string(), deep_id, Some(BigDecimal::from(104)), None).unwrap();
+    exchange.cancel_order(maker, "BTC-PERP".to_string(), resting_id).unwrap();
+    assert!(exchange.cancel_order(taker, "BTC-PERP".to_string(), Uuid::new_v4()).is_err());
+    exchange.request_withdrawal(taker, "USDT".to_string(), BigDecimal::from(100)).unwrap();
+
+    clock.advance(Duration::hours(8));
+    mark(&mut exchange, 102);
+    exchange.expire_orders().unwrap();
+    exchange.run_funding().unwrap();
+
+    (clock, exchange)
+}
+
+#[test]
+fn replay_rebuilds_the_same_exchange() {
+    let path = journal_path("replay");
+    let (clock, mut exchange) = journaled_session(&path);
+
+    // a 50x long that the next mark liquidates into the maker's bid, with fees posted against each fill
+    exchange.set_fee_schedule("BTC-PERP".to_string(), FeeSchedule::flat(decimal("0.0002"), decimal("0.0005"))).unwrap();
+    let (maker, gambler, closer) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    for (user_id, amount) in [(maker, 10_000), (gambler, 100), (closer, 10_000)] {
+        exchange.create_account(user_id).unwrap();
+        exchange.deposit(user_id, "USDT".to_string(), BigDecimal::from(amount)).unwrap();
+    }
+
+    // one fill shrinking the position trims both reduce-only orders, each trim taking a sequence
+    exchange.place_order(order(&exchange, maker, Side::Sell, 100, 3, None)).unwrap();
+    exchange.place_order(order(&exchange, closer, Side::Buy, 100, 3, None)).unwrap();
+    for price in [110, 111] {
+        let mut close = order(&exchange, closer, Side::Sell, price, 3, None);
+        close.reduce_only = true;
+        exchange.place_order(close).unwrap();
+    }
+    exchange.place_order(order(&exchange, maker, Side::Buy, 98, 2, None)).unwrap();
+    exchange.place_order(order(&exchange, closer, Side::Sell, 98, 2, None)).unwrap();
+
+    exchange.place_order(order(&exchange, maker, Side::Sell, 100, 10, None)).unwrap();
+    exchange.place_order(order(&exchange, gambler, Side::Buy, 100, 10, Some(50))).unwrap();
+    exchange.place_order(order(&exchange, maker, Side::Buy, 99, 10, None)).unwrap();
+    clock.advance(Duration::seconds(1));
+    mark(&mut exchange, 97);
+    assert_eq!(exchange.get_account(gambler).unwrap().positions["BTC-PERP"].quantity, BigDecimal::from(0));
+
+    let replayed = Exchange::replay(&path, exchange.clock.clone()).unwrap();
+    assert_eq!(state(&replayed), state(&exchange));
+    assert_eq!(exchange.funding_calculator.get_funding_payments().len(), 2);
+    assert_eq!(replayed.get_last_trade_price("BTC-PERP"), exchange.get_last_trade_price("BTC-PERP"));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn journal_records_rejected_commands_and_continues_after_reopen() {
+    let path = journal_path("reopen");
+    let (clock, exchange) = journaled_session(&path);
+    let written = exchange.journal.as_ref().unwrap().last_sequence();
+
+    let entries = Journal::read(&path).unwrap();
+    assert_eq!(entries.len() as u64, written);
+    assert!(matches!(entries[0].command, Command::Genesis { .. }));
+    assert!(entries.iter().zip(1..).all(|(entry, sequence)| entry.sequence == sequence));
+    drop(exchange);
+
+    let mut resumed = Exchange::replay(&path, clock.clone()).unwrap();
+    resumed.attach_journal(Journal::open(&path).unwrap());
+    clock.advance(Duration::seconds(1));
+    mark(&mut resumed, 103);
+    assert_eq!(resumed.journal.as_ref().unwrap().last_sequence(), written + 1);
+
+    let replayed = Exchange::replay(&path, clock.clone()).unwrap();
+    assert_eq!(state(&replayed), state(&resumed));
+    assert!(Exchange::with_journal(vec![spec()], Duration::hours(8), "USDT".to_string(), clock, Journal::open(&path).unwrap()).is_err());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn snapshots_round_trip_in_both_formats() {
+    let path = journal_path("snapshot");
+    let (clock, exchange) = journaled_session(&path);
+
+    for format in [SnapshotFormat::Json, SnapshotFormat::Cbor] {
+        let bytes = exchange.snapshot(format).unwrap();
+        let restored = Exchange::restore(&bytes, format, clock.clone()).unwrap();
+        assert_eq!(state(&restored), state(&exchange));
+        assert_eq!(restored.journal_sequence, exchange.journal_sequence);
+        assert!(restored.journal.is_none());
+    }
+
+    let json = String::from_utf8(exchange.snapshot(SnapshotFormat::Json).unwrap()).unwrap();
+    let future = json.replacen(&format!("\"version\":{}", SNAPSHOT_VERSION), "\"version\":99", 1);
+    assert!(Exchange::restore(future.as_bytes(), SnapshotFormat::Json, clock).is_err());
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn snapshot_plus_journal_tail_matches_never_stopping() {
+    let path = journal_path("recover");
+    let (clock, mut exchange) = journaled_session(&path);
+    let bytes = exchange.snapshot(SnapshotFormat::Cbor).unwrap();
+
+    let buyer = Uuid::new_v4();
+    exchange.create_account(buyer).unwrap();
+    exchange.deposit(buyer, "USDT".to_string(), BigDecimal::from(5_000)).unwrap();
+    clock.advance(Duration::seconds(5));
+    mark(&mut exchange, 104);
+    exchange.place_order(order(&exchange, buyer, Side::Buy, 104, 2, None)).unwrap();
+    clock.advance(Duration::hours(8));
+    mark(&mut exchange, 104);
+    exchange.run_funding().unwrap();
+
+    let recovered = Exchange::recover(&bytes, SnapshotFormat::Cbor, &path, clock).unwrap();
+    assert_eq!(state(&recovered), state(&exchange));
+    assert_eq!(recovered.journal_sequence, exchange.journal_sequence);
+    assert_eq!(recovered.funding_calculator.get_funding_history().len(), 2);
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use bigdecimal::BigDecimal;
+use chrono::Duration;
+use common::{decimal, funded, mark, order};
+use order_book::clock::SimulatedClock;
+use order_book::exchange::Exchange;
+use order_book::fees::FeeSchedule;
+use order_book::ledger::{LedgerAccount, LedgerReason, LedgerReference};
+use order_book::models::Side;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/**
+ * long and maker with 1,000 USDT each, 2 bps maker and 5 bps taker
+ */
+fn setup() -> (Arc<SimulatedClock>, Exchange, Uuid, Uuid) {
+    let (clock, mut exchange, [long, maker]) = funded([1_000, 1_000]);
+    exchange.set_fee_schedule("BTC-PERP".to_string(), FeeSchedule::flat(decimal("0.0002"), decimal("0.0005"))).unwrap();
+    (clock, exchange, long, maker)
+}
+
+fn assert_books_balance(exchange: &Exchange) {
+    exchange.verify_ledger().unwrap();
+    assert!(exchange.trial_balance().values().all(|total| total == &BigDecimal::from(0)));
+}
+
+#[test]
+fn trades_post_fees_and_realized_pnl_against_the_trade() {
+    let (_, mut exchange, long, maker) = setup();
+    exchange.place_order(order(&exchange, maker, Side::Sell, 100, 2, None)).unwrap();
+    exchange.place_order(order(&exchange, long, Side::Buy, 100, 2, Some(10))).unwrap();
+    exchange.place_order(order(&exchange, maker, Side::Buy, 110, 2, None)).unwrap();
+    let close = exchange.place_order(order(&exchange, long, Side::Sell, 110, 2, Some(10))).unwrap();
+    let trade_id = close.trades[0].id;
+
+    let closing: Vec<_> = exchange.ledger.entries().iter()
+        .filter(|entry| entry.reference == Some(LedgerReference::Trade(trade_id)))
+        .map(|entry| (entry.reason, entry.debit, entry.credit, entry.amount.clone()))
+        .collect();
+    assert_eq!(closing, vec![
+        (LedgerReason::RealizedPnl, LedgerAccount::User(maker), LedgerAccount::Settlement, BigDecimal::from(20)),
+        (LedgerReason::RealizedPnl, LedgerAccount::Settlement, LedgerAccount::User(long), BigDecimal::from(20)),
+        (LedgerReason::Fee, LedgerAccount::User(maker), LedgerAccount::FeeRevenue, decimal("0.044")),
+        (LedgerReason::Fee, LedgerAccount::User(long), LedgerAccount::FeeRevenue, decimal("0.11")),
+    ]);
+
+    // 0.04 + 0.1 on the way in, 0.044 + 0.11 on the way out
+    assert_eq!(exchange.ledger.balance(LedgerAccount::FeeRevenue, "USDT"), decimal("0.294"));
+    assert_eq!(exchange.ledger.balance(LedgerAccount::Settlement, "USDT"), BigDecimal::from(0));
+    assert_books_balance(&exchange);
+}
+
+#[test]
+fn funding_moves_balances_from_longs_to_shorts() {
+    let (clock, mut exchange, long, maker) = setup();
+    exchange.place_order(order(&exchange, maker, Side::Sell, 100, 2, None)).unwrap();
+    exchange.place_order(order(&exchange, long, Side::Buy, 100, 2, Some(10))).unwrap();
+    let before = exchange.get_account(long).unwrap().get_balance("USDT");
+
+    clock.advance(Duration::hours(8));
+    mark(&mut exchange, 100);
+    exchange.run_funding().unwrap();
+
+    // 1 bp of the 200 notional
+    assert_eq!(exchange.get_account(long).unwrap().get_balance("USDT"), before - decimal("0.02"));
+    let funding: Vec<_> = exchange.ledger.entries_for(LedgerAccount::Funding)
+        .map(|entry| (entry.debit, entry.credit, entry.amount.clone()))
+        .collect();
+    assert_eq!(funding.len(), 2);
+    assert!(funding.contains(&(LedgerAccount::User(long), LedgerAccount::Funding, decimal("0.02"))));
+    assert!(funding.contains(&(LedgerAccount::Funding, LedgerAccount::User(maker), decimal("0.02"))));
+    assert_books_balance(&exchange);
+}
+
+#[test]
+fn liquidations_and_the_insurance_fund_go_through_the_ledger() {
+    let (_, mut exchange, long, maker) = setup();
+    exchange.seed_insurance_fund("USDT".to_string(), BigDecimal::from(5)).unwrap();
+    exchange.place_order(order(&exchange, maker, Side::Sell, 100, 2, None)).unwrap();
+    exchange.place_order(order(&exchange, long, Side::Buy, 100, 2, Some(10))).unwrap();
+    exchange.place_order(order(&exchange, maker, Side::Buy, 88, 2, None)).unwrap();
+    mark(&mut exchange, 90);
+
+    let payouts: Vec<_> = exchange.ledger.entries().iter()
+        .filter(|entry| entry.reason == LedgerReason::InsurancePayout)
+        .collect();
+    assert_eq!(payouts.len(), 1);
+    assert_eq!(payouts[0].amount, BigDecimal::from(4));
+    assert_eq!(payouts[0].reference, Some(LedgerReference::Liquidation { symbol: "BTC-PERP".to_string(), user_id: long }));
+    assert_eq!(exchange.ledger.balance(LedgerAccount::InsuranceFund, "USDT"), exchange.insurance_fund_balance("USDT"));
+    assert_books_balance(&exchange);
+}
+
+#[test]
+fn withdrawals_and_transfers_balance() {
+    let (_, mut exchange, long, maker) = setup();
+    exchange.transfer(long, maker, "USDT".to_string(), BigDecimal::from(250)).unwrap();
+    assert!(exchange.transfer(long, maker, "USDT".to_string(), BigDecimal::from(751)).is_err());
+
+    let paid = exchange.request_withdrawal(maker, "USDT".to_string(), BigDecimal::from(300)).unwrap();
+    let pending = exchange.request_withdrawal(maker, "USDT".to_string(), BigDecimal::from(100)).unwrap();
+    exchange.approve_withdrawal(paid).unwrap();
+    exchange.complete_withdrawal(paid).unwrap();
+    assert_eq!(exchange.ledger.balance(LedgerAccount::PendingWithdrawals, "USDT"), BigDecimal::from(100));
+    assert_books_balance(&exchange);
+
+    exchange.reject_withdrawal(pending, "duplicate".to_string()).unwrap();
+    assert_eq!(exchange.get_account(maker).unwrap().get_balance("USDT"), BigDecimal::from(950));
+    // deposits in, one payout out
+    assert_eq!(exchange.ledger.balance(LedgerAccount::External, "USDT"), BigDecimal::from(-1_700));
+    assert_books_balance(&exchange);
+}
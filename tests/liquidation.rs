@@ -0,0 +1,175 @@
+mod common;
+
+use bigdecimal::BigDecimal;
+use common::{funded, mark, order};
+use order_book::exchange::Exchange;
+use order_book::models::Side;
+use uuid::Uuid;
+
+/**
+ * long holds 2 @ 100 on 10x: liquidation price 90.6, bankruptcy price 90
+ */
+fn setup() -> (Exchange, Uuid, Uuid) {
+    let (_, mut exchange, [long, maker]) = funded([1_000, 1_000]);
+    exchange.place_order(order(&exchange, maker, Side::Sell, 100, 2, None)).unwrap();
+    exchange.place_order(order(&exchange, long, Side::Buy, 100, 2, Some(10))).unwrap();
+    (exchange, long, maker)
+}
+
+#[test]
+fn mark_price_above_liquidation_price_leaves_position_alone() {
+    let (mut exchange, long, _) = setup();
+    let result = mark(&mut exchange, 91);
+
+    assert!(result.liquidations.is_empty());
+    assert_eq!(exchange.get_account(long).unwrap().positions["BTC-PERP"].quantity, BigDecimal::from(2));
+}
+
+#[test]
+fn liquidation_cancels_open_orders_and_closes_into_the_book() {
+    let (mut exchange, long, maker) = setup();
+    let resting = order(&exchange, long, Side::Buy, 80, 1, Some(10));
+    let resting_id = resting.id;
+    exchange.place_order(resting).unwrap();
+    exchange.place_order(order(&exchange, maker, Side::Buy, 90, 2, None)).unwrap();
+
+    let result = mark(&mut exchange, 90);
+
+    assert_eq!(result.liquidations.len(), 1);
+    let event = &result.liquidations[0];
+    assert_eq!(event.user_id, long);
+    assert_eq!(event.bankruptcy_price, BigDecimal::from(90));
+    assert_eq!(event.fill_price, Some(BigDecimal::from(90)));
+    assert_eq!(event.remaining_quantity, BigDecimal::from(0));
+    assert_eq!(event.deficit, BigDecimal::from(0));
+    assert!(result.deleverages.is_empty());
+
+    assert!(exchange.get_order("BTC-PERP", resting_id).is_none());
+    assert_eq!(exchange.get_account(long).unwrap().positions["BTC-PERP"].quantity, BigDecimal::from(0));
+    assert_eq!(exchange.get_account(maker).unwrap().positions["BTC-PERP"].quantity, BigDecimal::from(0));
+}
+
+#[test]
+fn empty_book_deleverages_the_opposite_position_at_the_bankruptcy_price() {
+    let (mut exchange, long, maker) = setup();
+    let result = mark(&mut exchange, 85);
+
+    let event = &result.liquidations[0];
+    assert_eq!(event.fill_price, None);
+    assert_eq!(event.deleveraged_quantity, BigDecimal::from(2));
+    assert_eq!(event.remaining_quantity, BigDecimal::from(0));
+
+    let adl = &result.deleverages[0];
+    assert_eq!((adl.user_id, adl.bankrupt_user_id, adl.side), (maker, long, Side::Sell));
+    assert_eq!((adl.price.clone(), adl.realized_pnl.clone()), (BigDecimal::from(90), BigDecimal::from(20)));
+    assert_eq!(exchange.get_account(long).unwrap().positions["BTC-PERP"].quantity, BigDecimal::from(0));
+    assert_eq!(exchange.get_account(maker).unwrap().get_balance("USDT"), BigDecimal::from(1_020));
+}
+
+/**
+ * cash across every account plus the insurance fund
+ */
+fn total_collateral(exchange: &Exchange) -> BigDecimal {
+    let balances: BigDecimal = exchange.accounts.values().map(|account| account.get_balance("USDT")).sum();
+    balances + exchange.insurance_fund_balance("USDT")
+}
+
+#[test]
+fn fill_above_bankruptcy_price_pays_the_surplus_into_the_fund() {
+    let (mut exchange, long, maker) = setup();
+    exchange.place_order(order(&exchange, maker, Side::Buy, 91, 2, None)).unwrap();
+
+    let event = mark(&mut exchange, 90).liquidations.remove(0);
+
+    assert_eq!(event.surplus, BigDecimal::from(2));
+    assert_eq!(event.deficit, BigDecimal::from(0));
+    // the trader loses exactly the 20 of margin: 18 of trading loss and 2 to the fund
+    assert_eq!(exchange.get_account(long).unwrap().get_balance("USDT"), BigDecimal::from(980));
+    assert_eq!(exchange.insurance_fund_balance("USDT"), BigDecimal::from(2));
+    let history = exchange.insurance_fund_history("USDT");
+    assert_eq!((history[0].amount.clone(), history[0].user_id), (BigDecimal::from(2), Some(long)));
+}
+
+#[test]
+fn seeded_fund_covers_fills_below_the_bankruptcy_price() {
+    let (mut exchange, long, maker) = setup();
+    exchange.seed_insurance_fund("USDT".to_string(), BigDecimal::from(5)).unwrap();
+    exchange.place_order(order(&exchange, maker, Side::Buy, 88, 2, None)).unwrap();
+
+    let event = mark(&mut exchange, 90).liquidations.remove(0);
+
+    assert_eq!(event.deficit, BigDecimal::from(4));
+    assert_eq!(event.insurance_payout, BigDecimal::from(4));
+    assert_eq!(exchange.insurance_fund_balance("USDT"), BigDecimal::from(1));
+    // 24 of trading loss, 4 of it paid by the fund
+    assert_eq!(exchange.get_account(long).unwrap().get_balance("USDT"), BigDecimal::from(980));
+
+    let balances: Vec<BigDecimal> = exchange.insurance_fund_history("USDT").iter().map(|entry| entry.balance.clone()).collect();
+    assert_eq!(balances, vec![BigDecimal::from(5), BigDecimal::from(1)]);
+}
+
+#[test]
+fn fund_too_small_for_the_book_leaves_the_rest_to_deleveraging() {
+    let (mut exchange, long, maker) = setup();
+    exchange.seed_insurance_fund("USDT".to_string(), BigDecimal::from(2)).unwrap();
+    exchange.place_order(order(&exchange, maker, Side::Buy, 89, 1, None)).unwrap();
+    exchange.place_order(order(&exchange, maker, Side::Buy, 80, 1, None)).unwrap();
+
+    // the fund can take fills down to 89, the bid at 80 is left alone
+    let result = mark(&mut exchange, 90);
+    let event = &result.liquidations[0];
+    assert_eq!(event.fill_price, Some(BigDecimal::from(89)));
+    assert_eq!(event.insurance_payout, BigDecimal::from(1));
+    assert_eq!(event.deleveraged_quantity, BigDecimal::from(1));
+    assert_eq!(result.deleverages[0].quantity, BigDecimal::from(1));
+    assert_eq!(exchange.get_account(long).unwrap().get_balance("USDT"), BigDecimal::from(980));
+}
+
+#[test]
+fn adl_quantile_ranks_profitable_positions_by_pnl_and_leverage() {
+    let (mut exchange, long, maker) = setup();
+    let (short, buyer) = (Uuid::new_v4(), Uuid::new_v4());
+    for user_id in [short, buyer] {
+        exchange.create_account(user_id).unwrap();
+        exchange.deposit(user_id, "USDT".to_string(), BigDecimal::from(1_000)).unwrap();
+    }
+    exchange.place_order(order(&exchange, short, Side::Sell, 100, 1, None)).unwrap();
+    exchange.place_order(order(&exchange, buyer, Side::Buy, 100, 1, None)).unwrap();
+
+    mark(&mut exchange, 95);
+    assert_eq!(exchange.adl_quantile(maker, "BTC-PERP"), Some(5));
+    assert_eq!(exchange.adl_quantile(short, "BTC-PERP"), Some(3));
+    assert_eq!(exchange.adl_quantile(long, "BTC-PERP"), None);
+    assert_eq!(exchange.adl_quantile(buyer, "BTC-PERP"), None);
+
+    // the top of the queue absorbs the whole bankrupt position
+    let result = mark(&mut exchange, 85);
+    assert_eq!(result.deleverages.len(), 1);
+    assert_eq!(result.deleverages[0].user_id, maker);
+    assert_eq!(exchange.get_account(short).unwrap().positions["BTC-PERP"].quantity, BigDecimal::from(1));
+}
+
+#[test]
+fn liquidations_conserve_total_collateral() {
+    for (bid, seed) in [(95, 0), (91, 0), (90, 3), (89, 0), (88, 1), (80, 50)] {
+        let (mut exchange, _, maker) = setup();
+        if seed > 0 {
+            exchange.seed_insurance_fund("USDT".to_string(), BigDecimal::from(seed)).unwrap();
+        }
+        let before = total_collateral(&exchange);
+
+        exchange.place_order(order(&exchange, maker, Side::Buy, bid, 1, None)).unwrap();
+        exchange.place_order(order(&exchange, maker, Side::Buy, bid - 1, 1, None)).unwrap();
+        let result = mark(&mut exchange, 90);
+
+        assert_eq!(result.liquidations.len(), 1, "bid {}", bid);
+        assert_eq!(total_collateral(&exchange), before, "bid {}", bid);
+    }
+}
+
+#[test]
+fn seeding_needs_a_positive_amount() {
+    let (mut exchange, _, _) = setup();
+    assert!(exchange.seed_insurance_fund("USDT".to_string(), BigDecimal::from(0)).is_err());
+    assert!(exchange.insurance_fund_history("USDT").is_empty());
+}
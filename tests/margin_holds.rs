@@ -0,0 +1,164 @@
+mod common;
+
+use bigdecimal::BigDecimal;
+use chrono::Duration;
+use common::{clock, funded, mark, mark_on, order, order_on, spec_for};
+use order_book::clock::Clock;
+use order_book::exchange::Exchange;
+use order_book::models::{OrderError, OrderType, PostOnly, Side, TimeInForce};
+use uuid::Uuid;
+
+fn held(exchange: &mut Exchange, user_id: Uuid) -> BigDecimal {
+    exchange.get_account(user_id).unwrap().held_margin("USDT")
+}
+
+#[test]
+fn accepted_order_holds_its_margin() {
+    let (_, mut exchange, [trader, _]) = funded([1_000, 1_000]);
+    exchange.place_order(order(&exchange, trader, Side::Buy, 100, 5, Some(10))).unwrap();
+
+    assert_eq!(held(&mut exchange, trader), BigDecimal::from(50));
+    assert_eq!(exchange.available_balance(trader, "USDT").unwrap(), BigDecimal::from(950));
+    assert_eq!(exchange.get_account(trader).unwrap().get_balance("USDT"), BigDecimal::from(1_000));
+
+    // the hold counts against the next order
+    assert!(exchange.place_order(order(&exchange, trader, Side::Buy, 100, 96, Some(10))).is_err());
+    assert!(exchange.place_order(order(&exchange, trader, Side::Buy, 100, 95, Some(10))).is_ok());
+}
+
+#[test]
+fn fills_move_the_hold_into_position_margin() {
+    let (_, mut exchange, [trader, maker]) = funded([1_000, 1_000]);
+    exchange.place_order(order(&exchange, trader, Side::Buy, 100, 5, Some(10))).unwrap();
+    exchange.place_order(order(&exchange, maker, Side::Sell, 100, 2, None)).unwrap();
+
+    assert_eq!(held(&mut exchange, trader), BigDecimal::from(30));
+    assert_eq!(exchange.available_balance(trader, "USDT").unwrap(), BigDecimal::from(950));
+}
+
+#[test]
+fn partial_cancel_releases_only_the_unfilled_share() {
+    let (_, mut exchange, [trader, maker]) = funded([1_000, 1_000]);
+    let resting = order(&exchange, trader, Side::Buy, 100, 5, Some(10));
+    let resting_id = resting.id;
+    exchange.place_order(resting).unwrap();
+    exchange.place_order(order(&exchange, maker, Side::Sell, 100, 2, None)).unwrap();
+    exchange.cancel_order(trader, "BTC-PERP".to_string(), resting_id).unwrap();
+
+    assert_eq!(held(&mut exchange, trader), BigDecimal::from(0));
+    assert_eq!(exchange.available_balance(trader, "USDT").unwrap(), BigDecimal::from(980));
+    assert_eq!(exchange.get_account(trader).unwrap().get_balance("USDT"), BigDecimal::from(1_000));
+}
+
+#[test]
+fn amend_re_holds_at_the_new_size() {
+    let (_, mut exchange, [trader, _]) = funded([1_000, 1_000]);
+    let resting = order(&exchange, trader, Side::Buy, 100, 5, Some(10));
+    let resting_id = resting.id;
+    exchange.place_order(resting).unwrap();
+
+    exchange.amend_order(trader, "BTC-PERP".to_string(), resting_id, None, Some(BigDecimal::from(8))).unwrap();
+    assert_eq!(held(&mut exchange, trader), BigDecimal::from(80));
+    exchange.amend_order(trader, "BTC-PERP".to_string(), resting_id, Some(BigDecimal::from(50)), None).unwrap();
+    assert_eq!(held(&mut exchange, trader), BigDecimal::from(40));
+}
+
+#[test]
+fn amended_stop_stays_held_at_its_trigger() {
+    let (_, mut exchange, [trader, _]) = funded([1_000, 1_000]);
+    let mut stop = order(&exchange, trader, Side::Buy, 0, 5, Some(10));
+    stop.order_type = OrderType::Stop;
+    stop.trigger_price = Some(BigDecimal::from(110));
+    let stop_id = stop.id;
+    exchange.place_order(stop).unwrap();
+    assert_eq!(held(&mut exchange, trader), BigDecimal::from(55));
+
+    exchange.amend_order(trader, "BTC-PERP".to_string(), stop_id, None, Some(BigDecimal::from(4))).unwrap();
+    assert_eq!(held(&mut exchange, trader), BigDecimal::from(44));
+}
+
+#[test]
+fn rejected_amend_keeps_the_order_and_its_hold() {
+    let (_, mut exchange, [trader, maker]) = funded([1_000, 1_000]);
+    exchange.place_order(order(&exchange, maker, Side::Sell, 105, 5, None)).unwrap();
+    let mut resting = order(&exchange, trader, Side::Buy, 100, 5, Some(10));
+    resting.post_only = Some(PostOnly::Reject);
+    let resting_id = resting.id;
+    exchange.place_order(resting).unwrap();
+
+    let result = exchange.amend_order(trader, "BTC-PERP".to_string(), resting_id, Some(BigDecimal::from(105)), None);
+    assert!(matches!(result, Err(OrderError::PostOnlyWouldCross)));
+    assert_eq!(exchange.get_order("BTC-PERP", resting_id).unwrap().price, BigDecimal::from(100));
+    assert_eq!(held(&mut exchange, trader), BigDecimal::from(50));
+
+    exchange.cancel_order(trader, "BTC-PERP".to_string(), resting_id).unwrap();
+    assert_eq!(exchange.available_balance(trader, "USDT").unwrap(), BigDecimal::from(1_000));
+}
+
+#[test]
+fn place_and_cancel_cycles_never_create_balance() {
+    let (clock, mut exchange, [trader, maker]) = funded([1_000, 1_000]);
+    let total = |exchange: &mut Exchange| -> BigDecimal {
+        [trader, maker].iter().map(|user_id| exchange.get_account(*user_id).unwrap().get_balance("USDT")).sum()
+    };
+    let before = total(&mut exchange);
+
+    for round in 0..20 {
+        let mut bid = order(&exchange, trader, Side::Buy, 90 + round % 5, 1 + round % 3, Some(5 + round % 4));
+        if round % 4 == 0 {
+            bid.time_in_force = TimeInForce::GTD(clock.now() + Duration::seconds(1));
+        }
+        let bid_id = bid.id;
+        exchange.place_order(bid).unwrap();
+        if round % 3 == 0 {
+            exchange.amend_order(trader, "BTC-PERP".to_string(), bid_id, None, Some(BigDecimal::from(4))).unwrap();
+        }
+
+        if round % 4 == 0 {
+            clock.advance(Duration::seconds(2));
+            mark(&mut exchange, 100);
+            assert_eq!(exchange.expire_orders().unwrap().len(), 1);
+        } else {
+            exchange.cancel_order(trader, "BTC-PERP".to_string(), bid_id).unwrap();
+        }
+
+        assert_eq!(total(&mut exchange), before, "round {}", round);
+        assert_eq!(held(&mut exchange, trader), BigDecimal::from(0), "round {}", round);
+        assert_eq!(exchange.available_balance(trader, "USDT").unwrap(), BigDecimal::from(1_000), "round {}", round);
+    }
+}
+
+#[test]
+fn orders_are_held_in_the_symbols_settlement_asset() {
+    let clock = clock();
+    let mut spec = spec_for("ETH-PERP");
+    spec.quote_asset = "USDC".to_string();
+    let mut exchange = Exchange::with_clock(vec![spec], Duration::hours(8), "USDT".to_string(), clock);
+    let trader = Uuid::new_v4();
+    exchange.create_account(trader).unwrap();
+    exchange.deposit(trader, "USDC".to_string(), BigDecimal::from(1_000)).unwrap();
+    mark_on(&mut exchange, "ETH-PERP", 100);
+
+    exchange.place_order(order_on(&exchange, trader, "ETH-PERP", Side::Buy, 100, 5, Some(10))).unwrap();
+    assert_eq!(exchange.get_account(trader).unwrap().held_margin("USDC"), BigDecimal::from(50));
+}
+
+#[test]
+fn unleveraged_orders_are_margined_at_full_notional() {
+    let (_, mut exchange, [trader, maker]) = funded([1_000, 1_000]);
+    let broke = Uuid::new_v4();
+    exchange.create_account(broke).unwrap();
+    let result = exchange.place_order(order(&exchange, broke, Side::Buy, 100, 1_000, None));
+    assert!(matches!(result, Err(OrderError::InsufficientBalance)));
+
+    exchange.place_order(order(&exchange, trader, Side::Buy, 100, 5, None)).unwrap();
+    assert_eq!(held(&mut exchange, trader), BigDecimal::from(500));
+    let result = exchange.place_order(order(&exchange, trader, Side::Buy, 100, 6, None));
+    assert!(matches!(result, Err(OrderError::InsufficientBalance)));
+
+    // the position it opens can be liquidated like any other
+    exchange.place_order(order(&exchange, maker, Side::Sell, 100, 5, None)).unwrap();
+    let position = exchange.get_account(trader).unwrap().positions["BTC-PERP"].clone();
+    assert_eq!(position.leverage, Some(BigDecimal::from(1)));
+    assert!(position.liquidation_price.is_some());
+}
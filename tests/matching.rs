@@ -0,0 +1,540 @@
+use bigdecimal::BigDecimal;
+use order_book::models::{Order, OrderBook, OrderError, OrderType, PostOnly, SelfTradePrevention, Side, TimeInForce, TriggerBy, Trade};
+use uuid::Uuid;
+
+fn limit(side: Side, price: u64, quantity: u64, time_in_force: TimeInForce) -> Order {
+    let now = chrono::Utc::now();
+    Order {
+        id: Uuid::new_v4(),
+        user_id: Uuid::new_v4(),
+        symbol: "BTC-PERP".to_string(),
+        side,
+        order_type: OrderType::Limit,
+        price: BigDecimal::from(price),
+        trigger_price: None,
+        trigger_by: TriggerBy::LastPrice,
+        quantity: BigDecimal::from(quantity),
+        filled_quantity: BigDecimal::from(0),
+        display_quantity: None,
+        leverage: None,
+        time_in_force,
+        post_only: None,
+        reduce_only: false,
+        self_trade_prevention: None,
+        created_at: now,
+        updated_at: now,
+        sequence: 0,
+        position_side: None,
+    }
+}
+
+/**
+ * 3 @ 100 and 2 @ 101 on the asks, 3 @ 99 and 2 @ 98 on the bids
+ */
+fn book() -> OrderBook {
+    let mut book = OrderBook::new("BTC-PERP".to_string());
+    book.add_order(limit(Side::Sell, 100, 3, TimeInForce::GTC)).unwrap();
+    book.add_order(limit(Side::Sell, 101, 2, TimeInForce::GTC)).unwrap();
+    book.add_order(limit(Side::Buy, 99, 3, TimeInForce::GTC)).unwrap();
+    book.add_order(limit(Side::Buy, 98, 2, TimeInForce::GTC)).unwrap();
+    book
+}
+
+fn post_only(side: Side, price: u64, quantity: u64, policy: PostOnly) -> Order {
+    let mut order = limit(side, price, quantity, TimeInForce::GTC);
+    order.post_only = Some(policy);
+    order
+}
+
+fn stop(side: Side, order_type: OrderType, trigger: u64, trigger_by: TriggerBy) -> Order {
+    let mut order = limit(side, trigger, 1, TimeInForce::GTC);
+    order.order_type = order_type;
+    order.trigger_price = Some(BigDecimal::from(trigger));
+    order.trigger_by = trigger_by;
+    order
+}
+
+fn traded(trades: &[Trade]) -> BigDecimal {
+    trades.iter().map(|t| t.quantity.clone()).sum()
+}
+
+#[test]
+fn ioc_buy_cancels_unfilled_remainder() {
+    let mut book = book();
+    let trades = book.add_order(limit(Side::Buy, 101, 8, TimeInForce::IOC)).unwrap().trades;
+
+    assert_eq!(traded(&trades), BigDecimal::from(5));
+    assert!(book.asks.is_empty());
+    let (bids, _) = book.get_depth(1);
+    assert_eq!(bids[0].0, BigDecimal::from(99));
+}
+
+#[test]
+fn ioc_sell_cancels_unfilled_remainder() {
+    let mut book = book();
+    let trades = book.add_order(limit(Side::Sell, 98, 8, TimeInForce::IOC)).unwrap().trades;
+
+    assert_eq!(traded(&trades), BigDecimal::from(5));
+    assert!(book.bids.is_empty());
+    let (_, asks) = book.get_depth(1);
+    assert_eq!(asks[0].0, BigDecimal::from(100));
+}
+
+#[test]
+fn ioc_with_exact_liquidity_fills_completely() {
+    let mut book = book();
+    let trades = book.add_order(limit(Side::Buy, 100, 3, TimeInForce::IOC)).unwrap().trades;
+    assert_eq!(traded(&trades), BigDecimal::from(3));
+
+    let trades = book.add_order(limit(Side::Sell, 99, 3, TimeInForce::IOC)).unwrap().trades;
+    assert_eq!(traded(&trades), BigDecimal::from(3));
+
+    assert_eq!(book.get_depth(1), (
+        vec![(BigDecimal::from(98), BigDecimal::from(2))],
+        vec![(BigDecimal::from(101), BigDecimal::from(2))],
+    ));
+}
+
+#[test]
+fn fok_buy_with_partial_liquidity_leaves_book_untouched() {
+    let mut book = book();
+    let before = book.get_depth(10);
+
+    let trades = book.add_order(limit(Side::Buy, 101, 6, TimeInForce::FOK)).unwrap().trades;
+
+    assert!(trades.is_empty());
+    assert_eq!(book.get_depth(10), before);
+}
+
+#[test]
+fn fok_sell_with_partial_liquidity_leaves_book_untouched() {
+    let mut book = book();
+    let before = book.get_depth(10);
+
+    // 5 resting bids in total, but only 3 at or above the limit
+    let trades = book.add_order(limit(Side::Sell, 99, 4, TimeInForce::FOK)).unwrap().trades;
+
+    assert!(trades.is_empty());
+    assert_eq!(book.get_depth(10), before);
+}
+
+#[test]
+fn fok_buy_with_exact_liquidity_fills_completely() {
+    let mut book = book();
+    let trades = book.add_order(limit(Side::Buy, 101, 5, TimeInForce::FOK)).unwrap().trades;
+
+    assert_eq!(traded(&trades), BigDecimal::from(5));
+    assert!(book.asks.is_empty());
+}
+
+#[test]
+fn fok_sell_with_exact_liquidity_fills_completely() {
+    let mut book = book();
+    let trades = book.add_order(limit(Side::Sell, 98, 5, TimeInForce::FOK)).unwrap().trades;
+
+    assert_eq!(traded(&trades), BigDecimal::from(5));
+    assert!(book.bids.is_empty());
+}
+
+#[test]
+fn post_only_buy_crossing_is_rejected() {
+    let mut book = book();
+    let before = book.get_depth(10);
+
+    let result = book.add_order(post_only(Side::Buy, 100, 1, PostOnly::Reject));
+
+    assert!(matches!(result, Err(OrderError::PostOnlyWouldCross)));
+    assert_eq!(book.get_depth(10), before);
+}
+
+#[test]
+fn post_only_sell_crossing_is_rejected() {
+    let mut book = book();
+    let before = book.get_depth(10);
+
+    let result = book.add_order(post_only(Side::Sell, 98, 1, PostOnly::Reject));
+
+    assert!(matches!(result, Err(OrderError::PostOnlyWouldCross)));
+    assert_eq!(book.get_depth(10), before);
+}
+
+#[test]
+fn post_only_buy_crossing_slides_behind_best_ask() {
+    let mut book = book();
+    book.tick_size = BigDecimal::from(1);
+    let order = post_only(Side::Buy, 101, 1, PostOnly::Slide);
+    let order_id = order.id;
+
+    let trades = book.add_order(order).unwrap().trades;
+
+    assert!(trades.is_empty());
+    assert_eq!(book.get_order(order_id).unwrap().price, BigDecimal::from(99));
+    // queued behind the bid already resting at 99
+    let (bids, asks) = book.get_depth(2);
+    assert_eq!(&bids[..], &[(BigDecimal::from(99), BigDecimal::from(3)), (BigDecimal::from(99), BigDecimal::from(1))]);
+    assert_eq!(asks[0], (BigDecimal::from(100), BigDecimal::from(3)));
+}
+
+#[test]
+fn post_only_sell_crossing_slides_behind_best_bid() {
+    let mut book = book();
+    book.tick_size = BigDecimal::from(1);
+    let order = post_only(Side::Sell, 98, 1, PostOnly::Slide);
+    let order_id = order.id;
+
+    let trades = book.add_order(order).unwrap().trades;
+
+    assert!(trades.is_empty());
+    assert_eq!(book.get_order(order_id).unwrap().price, BigDecimal::from(100));
+    let (bids, asks) = book.get_depth(2);
+    assert_eq!(bids[0], (BigDecimal::from(99), BigDecimal::from(3)));
+    assert_eq!(&asks[..], &[(BigDecimal::from(100), BigDecimal::from(3)), (BigDecimal::from(100), BigDecimal::from(1))]);
+}
+
+#[test]
+fn post_only_not_crossing_rests_at_its_price() {
+    let mut book = book();
+    let bid = post_only(Side::Buy, 99, 1, PostOnly::Reject);
+    let ask = post_only(Side::Sell, 101, 1, PostOnly::Slide);
+    let (bid_id, ask_id) = (bid.id, ask.id);
+
+    assert!(book.add_order(bid).unwrap().trades.is_empty());
+    assert!(book.add_order(ask).unwrap().trades.is_empty());
+
+    assert_eq!(book.get_order(bid_id).unwrap().price, BigDecimal::from(99));
+    assert_eq!(book.get_order(ask_id).unwrap().price, BigDecimal::from(101));
+}
+
+/**
+ * a resting ask of 3 @ 100 and an incoming bid of 5 @ 100 from the same user
+ */
+fn self_cross(mode: SelfTradePrevention) -> (OrderBook, Order, Order) {
+    let mut book = OrderBook::new("BTC-PERP".to_string());
+    let user_id = Uuid::new_v4();
+    let mut ask = limit(Side::Sell, 100, 3, TimeInForce::GTC);
+    ask.user_id = user_id;
+    book.add_order(ask.clone()).unwrap();
+
+    let mut bid = limit(Side::Buy, 100, 5, TimeInForce::GTC);
+    bid.user_id = user_id;
+    bid.self_trade_prevention = Some(mode);
+    (book, ask, bid)
+}
+
+#[test]
+fn self_trade_cancel_newest_keeps_resting_order() {
+    let (mut book, ask, bid) = self_cross(SelfTradePrevention::CancelNewest);
+    let result = book.add_order(bid.clone()).unwrap();
+
+    assert!(result.trades.is_empty());
+    assert_eq!(result.self_trades[0].taker_cancelled_quantity, BigDecimal::from(5));
+    assert_eq!(result.self_trades[0].maker_cancelled_quantity, BigDecimal::from(0));
+    assert!(book.get_order(ask.id).is_some());
+    assert!(book.get_order(bid.id).is_none());
+}
+
+#[test]
+fn self_trade_cancel_oldest_rests_incoming_order() {
+    let (mut book, ask, bid) = self_cross(SelfTradePrevention::CancelOldest);
+    let result = book.add_order(bid.clone()).unwrap();
+
+    assert!(result.trades.is_empty());
+    assert_eq!(result.self_trades[0].maker_cancelled_quantity, BigDecimal::from(3));
+    assert!(book.get_order(ask.id).is_none());
+    assert_eq!(book.get_order(bid.id).unwrap().remaining_quantity(), BigDecimal::from(5));
+}
+
+#[test]
+fn self_trade_cancel_both_empties_book() {
+    let (mut book, _, bid) = self_cross(SelfTradePrevention::CancelBoth);
+    let result = book.add_order(bid).unwrap();
+
+    assert!(result.trades.is_empty());
+    assert_eq!(result.self_trades.len(), 1);
+    assert!(book.orders.is_empty());
+    assert!(book.bids.is_empty() && book.asks.is_empty());
+}
+
+#[test]
+fn self_trade_decrement_and_cancel_shrinks_larger_order() {
+    let (mut book, ask, bid) = self_cross(SelfTradePrevention::DecrementAndCancel);
+    let result = book.add_order(bid.clone()).unwrap();
+
+    assert!(result.trades.is_empty());
+    assert_eq!(result.self_trades[0].taker_cancelled_quantity, BigDecimal::from(3));
+    assert_eq!(result.self_trades[0].maker_cancelled_quantity, BigDecimal::from(3));
+    assert!(book.get_order(ask.id).is_none());
+    assert_eq!(book.get_order(bid.id).unwrap().remaining_quantity(), BigDecimal::from(2));
+}
+
+#[test]
+fn self_trade_prevention_still_matches_other_users() {
+    let (mut book, _, bid) = self_cross(SelfTradePrevention::CancelOldest);
+    book.add_order(limit(Side::Sell, 100, 2, TimeInForce::GTC)).unwrap();
+
+    let result = book.add_order(bid).unwrap();
+
+    assert_eq!(traded(&result.trades), BigDecimal::from(2));
+    assert_eq!(result.self_trades.len(), 1);
+}
+
+/**
+ * other 5 @ 99, own 5 @ 100, other 5 @ 101, and an FOK bid of 10 @ 101 from the owner
+ */
+fn fok_through_own_order(mode: SelfTradePrevention) -> (OrderBook, Order) {
+    let mut book = OrderBook::new("BTC-PERP".to_string());
+    let user_id = Uuid::new_v4();
+    book.add_order(limit(Side::Sell, 99, 5, TimeInForce::GTC)).unwrap();
+    let mut own = limit(Side::Sell, 100, 5, TimeInForce::GTC);
+    own.user_id = user_id;
+    book.add_order(own).unwrap();
+    book.add_order(limit(Side::Sell, 101, 5, TimeInForce::GTC)).unwrap();
+
+    let mut bid = limit(Side::Buy, 101, 10, TimeInForce::FOK);
+    bid.user_id = user_id;
+    bid.self_trade_prevention = Some(mode);
+    (book, bid)
+}
+
+#[test]
+fn fok_stopped_short_by_self_trade_prevention_fills_nothing() {
+    for mode in [SelfTradePrevention::CancelNewest, SelfTradePrevention::CancelBoth, SelfTradePrevention::DecrementAndCancel] {
+        let (mut book, bid) = fok_through_own_order(mode);
+        let before = book.get_depth(10);
+
+        let result = book.add_order(bid).unwrap();
+
+        assert!(result.trades.is_empty(), "{:?}", mode);
+        assert_eq!(book.get_depth(10), before, "{:?}", mode);
+    }
+}
+
+#[test]
+fn fok_skipping_own_orders_fills_around_them() {
+    let (mut book, bid) = fok_through_own_order(SelfTradePrevention::CancelOldest);
+    let result = book.add_order(bid).unwrap();
+
+    assert_eq!(traded(&result.trades), BigDecimal::from(10));
+    assert!(book.asks.is_empty());
+}
+
+#[test]
+fn amend_quantity_down_keeps_queue_priority() {
+    let mut book = OrderBook::new("BTC-PERP".to_string());
+    let first = limit(Side::Sell, 100, 3, TimeInForce::GTC);
+    let second = limit(Side::Sell, 100, 3, TimeInForce::GTC);
+    let (first_id, second_id) = (first.id, second.id);
+    book.add_order(first).unwrap();
+    book.add_order(second).unwrap();
+
+    book.amend_order(first_id, None, Some(BigDecimal::from(2))).unwrap();
+    let trades = book.add_order(limit(Side::Buy, 100, 2, TimeInForce::IOC)).unwrap().trades;
+
+    assert_eq!(trades[0].seller_order_id, first_id);
+    assert!(book.get_order(first_id).is_none());
+    assert_eq!(book.get_depth(1).1[0], (BigDecimal::from(100), BigDecimal::from(3)));
+    assert!(book.get_order(second_id).is_some());
+}
+
+#[test]
+fn amend_quantity_up_loses_queue_priority() {
+    let mut book = OrderBook::new("BTC-PERP".to_string());
+    let first = limit(Side::Sell, 100, 3, TimeInForce::GTC);
+    let second = limit(Side::Sell, 100, 3, TimeInForce::GTC);
+    let (first_id, second_id) = (first.id, second.id);
+    book.add_order(first).unwrap();
+    book.add_order(second).unwrap();
+
+    book.amend_order(first_id, None, Some(BigDecimal::from(4))).unwrap();
+    let trades = book.add_order(limit(Side::Buy, 100, 3, TimeInForce::IOC)).unwrap().trades;
+
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0].seller_order_id, second_id);
+    assert_eq!(book.get_order(first_id).unwrap().remaining_quantity(), BigDecimal::from(4));
+}
+
+#[test]
+fn amend_price_through_the_spread_matches() {
+    let mut book = book();
+    let bid = limit(Side::Buy, 97, 2, TimeInForce::GTC);
+    let bid_id = bid.id;
+    book.add_order(bid).unwrap();
+
+    let trades = book.amend_order(bid_id, Some(BigDecimal::from(100)), None).unwrap().trades;
+
+    assert_eq!(traded(&trades), BigDecimal::from(2));
+    assert!(book.get_order(bid_id).is_none());
+}
+
+#[test]
+fn amend_refused_by_post_only_keeps_the_order_in_place() {
+    let mut book = OrderBook::new("BTC-PERP".to_string());
+    book.add_order(limit(Side::Sell, 100, 3, TimeInForce::GTC)).unwrap();
+    let first = post_only(Side::Buy, 97, 2, PostOnly::Reject);
+    let second = limit(Side::Buy, 97, 2, TimeInForce::GTC);
+    let (first_id, second_id) = (first.id, second.id);
+    book.add_order(first).unwrap();
+    book.add_order(second).unwrap();
+
+    let result = book.amend_order(first_id, Some(BigDecimal::from(100)), None);
+
+    assert!(matches!(result, Err(OrderError::PostOnlyWouldCross)));
+    assert_eq!(book.get_order(first_id).unwrap().price, BigDecimal::from(97));
+    // still ahead of the order queued after it
+    let trades = book.add_order(limit(Side::Sell, 97, 2, TimeInForce::IOC)).unwrap().trades;
+    assert_eq!(trades[0].buyer_order_id, first_id);
+    assert!(book.get_order(second_id).is_some());
+}
+
+fn iceberg(side: Side, price: u64, quantity: u64, display: u64) -> Order {
+    let mut order = limit(side, price, quantity, TimeInForce::GTC);
+    order.display_quantity = Some(BigDecimal::from(display));
+    order
+}
+
+#[test]
+fn iceberg_only_shows_its_slice_in_depth() {
+    let mut book = OrderBook::new("BTC-PERP".to_string());
+    book.add_order(iceberg(Side::Sell, 100, 10, 2)).unwrap();
+
+    assert_eq!(book.get_depth(1).1[0], (BigDecimal::from(100), BigDecimal::from(2)));
+}
+
+#[test]
+fn iceberg_slice_refresh_goes_behind_other_orders() {
+    let mut book = OrderBook::new("BTC-PERP".to_string());
+    let hidden = iceberg(Side::Sell, 100, 10, 2);
+    let plain = limit(Side::Sell, 100, 3, TimeInForce::GTC);
+    let (hidden_id, plain_id) = (hidden.id, plain.id);
+    book.add_order(hidden).unwrap();
+    book.add_order(plain).unwrap();
+
+    let trades = book.add_order(limit(Side::Buy, 100, 4, TimeInForce::IOC)).unwrap().trades;
+
+    // first slice, then the plain order that was queued before the refreshed slice
+    assert_eq!(trades.len(), 2);
+    assert_eq!((trades[0].seller_order_id, trades[0].quantity.clone()), (hidden_id, BigDecimal::from(2)));
+    assert_eq!((trades[1].seller_order_id, trades[1].quantity.clone()), (plain_id, BigDecimal::from(2)));
+    // the plain order's last 1 ahead of the refreshed slice
+    assert_eq!(book.get_depth(2).1, vec![(BigDecimal::from(100), BigDecimal::from(1)), (BigDecimal::from(100), BigDecimal::from(2))]);
+}
+
+#[test]
+fn aggressive_order_can_take_hidden_quantity() {
+    let mut book = OrderBook::new("BTC-PERP".to_string());
+    let hidden = iceberg(Side::Sell, 100, 10, 2);
+    let hidden_id = hidden.id;
+    book.add_order(hidden).unwrap();
+
+    let trades = book.add_order(limit(Side::Buy, 100, 7, TimeInForce::FOK)).unwrap().trades;
+
+    assert_eq!(traded(&trades), BigDecimal::from(7));
+    assert_eq!(book.get_order(hidden_id).unwrap().remaining_quantity(), BigDecimal::from(3));
+    assert_eq!(book.get_depth(1).1[0], (BigDecimal::from(100), BigDecimal::from(1)));
+}
+
+#[test]
+fn amended_gtd_order_expires_once() {
+    let mut book = OrderBook::new("BTC-PERP".to_string());
+    let expiry = chrono::Utc::now() + chrono::Duration::minutes(1);
+    let order = limit(Side::Buy, 99, 3, TimeInForce::GTD(expiry));
+    let order_id = order.id;
+    book.add_order(order).unwrap();
+    book.amend_order(order_id, None, Some(BigDecimal::from(5))).unwrap();
+
+    assert_eq!(book.take_expired(expiry), vec![order_id]);
+    assert!(book.take_expired(expiry).is_empty());
+}
+
+#[test]
+fn an_id_already_on_the_book_is_refused() {
+    let mut book = OrderBook::new("BTC-PERP".to_string());
+    let resting = limit(Side::Sell, 100, 2, TimeInForce::GTC);
+    let stop = stop(Side::Buy, OrderType::Stop, 105, TriggerBy::LastPrice);
+    book.add_order(resting.clone()).unwrap();
+    book.add_order(stop.clone()).unwrap();
+
+    assert!(matches!(book.add_order(resting.clone()), Err(OrderError::InvalidOrder)));
+    assert!(matches!(book.add_order(stop), Err(OrderError::InvalidOrder)));
+    assert_eq!(book.sequence, 2);
+
+    // the one copy fills and leaves nothing behind to match against
+    let trades = book.add_order(limit(Side::Buy, 100, 5, TimeInForce::IOC)).unwrap().trades;
+    assert_eq!(traded(&trades), BigDecimal::from(2));
+    assert!(book.asks.is_empty());
+}
+
+#[test]
+fn every_book_event_takes_the_next_sequence() {
+    let mut book = book();
+    assert_eq!(book.sequence, 4);
+
+    let buy = limit(Side::Buy, 101, 4, TimeInForce::GTC);
+    let buy_id = buy.id;
+    let trades = book.add_order(buy).unwrap().trades;
+
+    // accepted at 5, then one sequence per trade
+    let sequences: Vec<u64> = trades.iter().map(|t| t.sequence).collect();
+    assert_eq!(sequences, vec![6, 7]);
+    assert!(book.get_order(buy_id).is_none());
+    assert_eq!(book.asks.values().next().unwrap().open_quantity, BigDecimal::from(1));
+
+    let resting = limit(Side::Sell, 105, 1, TimeInForce::GTC);
+    let resting_id = resting.id;
+    book.add_order(resting).unwrap();
+    assert_eq!(book.get_order(resting_id).unwrap().sequence, 8);
+
+    book.amend_order(resting_id, Some(BigDecimal::from(106)), None).unwrap();
+    assert_eq!(book.get_order(resting_id).unwrap().sequence, 9);
+    assert_eq!(book.cancel_order(resting_id).unwrap().sequence, 10);
+    assert_eq!(book.sequence, 10);
+}
+
+#[test]
+fn last_price_stops_fire_once_the_trade_price_crosses() {
+    let mut book = OrderBook::new("BTC-PERP".to_string());
+    let buy = stop(Side::Buy, OrderType::Stop, 105, TriggerBy::LastPrice);
+    let sell = stop(Side::Sell, OrderType::StopLimit, 95, TriggerBy::LastPrice);
+    let (buy_id, sell_id) = (buy.id, sell.id);
+    book.add_order(buy).unwrap();
+    book.add_order(sell).unwrap();
+
+    // nothing printed yet, and the mark is not what these stops watch
+    assert!(book.trigger_stops(&BigDecimal::from(0), &BigDecimal::from(200)).is_empty());
+    assert!(book.trigger_stops(&BigDecimal::from(104), &BigDecimal::from(200)).is_empty());
+
+    let fired = book.trigger_stops(&BigDecimal::from(105), &BigDecimal::from(100));
+    assert_eq!(fired.iter().map(|o| (o.id, o.order_type)).collect::<Vec<_>>(), vec![(buy_id, OrderType::Market)]);
+
+    let fired = book.trigger_stops(&BigDecimal::from(94), &BigDecimal::from(100));
+    assert_eq!(fired.iter().map(|o| (o.id, o.order_type)).collect::<Vec<_>>(), vec![(sell_id, OrderType::Limit)]);
+    assert!(book.stop_orders.is_empty());
+}
+
+#[test]
+fn mark_price_stops_ignore_the_last_price() {
+    let mut book = OrderBook::new("BTC-PERP".to_string());
+    let sell = stop(Side::Sell, OrderType::Stop, 95, TriggerBy::MarkPrice);
+    let sell_id = sell.id;
+    book.add_order(sell).unwrap();
+
+    assert!(book.trigger_stops(&BigDecimal::from(90), &BigDecimal::from(96)).is_empty());
+    let fired = book.trigger_stops(&BigDecimal::from(100), &BigDecimal::from(95));
+    assert_eq!(fired.iter().map(|o| o.id).collect::<Vec<_>>(), vec![sell_id]);
+}
+
+#[test]
+fn stops_created_together_fire_in_placement_order() {
+    let mut book = OrderBook::new("BTC-PERP".to_string());
+    let created_at = chrono::Utc::now();
+    let mut placed = Vec::new();
+    for _ in 0..8 {
+        let mut order = stop(Side::Buy, OrderType::Stop, 105, TriggerBy::LastPrice);
+        order.created_at = created_at;
+        placed.push(order.id);
+        book.add_order(order).unwrap();
+    }
+
+    let fired = book.trigger_stops(&BigDecimal::from(110), &BigDecimal::from(110));
+    assert_eq!(fired.iter().map(|o| o.id).collect::<Vec<_>>(), placed);
+}
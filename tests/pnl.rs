@@ -0,0 +1,113 @@
+mod common;
+
+use bigdecimal::BigDecimal;
+use chrono::Duration;
+use common::{decimal, funded, mark, order};
+use order_book::clock::SimulatedClock;
+use order_book::exchange::Exchange;
+use order_book::fees::FeeSchedule;
+use order_book::models::Side;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/**
+ * long opens 2 @ 100 on 10x against the maker
+ */
+fn setup() -> (Arc<SimulatedClock>, Exchange, Uuid, Uuid) {
+    let (clock, mut exchange, [long, maker]) = funded([1_000, 1_000]);
+    exchange.place_order(order(&exchange, maker, Side::Sell, 100, 2, None)).unwrap();
+    exchange.place_order(order(&exchange, long, Side::Buy, 100, 2, Some(10))).unwrap();
+    (clock, exchange, long, maker)
+}
+
+#[test]
+fn opening_fills_realize_nothing_and_mark_to_market() {
+    let (_, mut exchange, long, maker) = setup();
+    mark(&mut exchange, 105);
+
+    let position = exchange.get_account(long).unwrap().positions["BTC-PERP"].clone();
+    assert_eq!(position.realized_pnl, BigDecimal::from(0));
+    assert_eq!(position.unrealized_pnl, BigDecimal::from(10));
+    assert_eq!(exchange.get_account(maker).unwrap().positions["BTC-PERP"].unrealized_pnl, BigDecimal::from(-10));
+    assert_eq!(exchange.get_account(long).unwrap().get_balance("USDT"), BigDecimal::from(1_000));
+}
+
+#[test]
+fn reductions_realize_into_the_balance() {
+    let (_, mut exchange, long, maker) = setup();
+    mark(&mut exchange, 105);
+    exchange.place_order(order(&exchange, maker, Side::Buy, 110, 1, None)).unwrap();
+    exchange.place_order(order(&exchange, long, Side::Sell, 110, 1, Some(10))).unwrap();
+
+    let position = exchange.get_account(long).unwrap().positions["BTC-PERP"].clone();
+    assert_eq!(position.realized_pnl, BigDecimal::from(10));
+    // the one left open is still valued at the mark, not the fill
+    assert_eq!(position.unrealized_pnl, BigDecimal::from(5));
+    assert_eq!(exchange.get_account(long).unwrap().get_balance("USDT"), BigDecimal::from(1_010));
+    assert_eq!(exchange.get_account(maker).unwrap().positions["BTC-PERP"].realized_pnl, BigDecimal::from(-10));
+}
+
+#[test]
+fn fees_and_funding_add_up_on_the_position() {
+    let (clock, mut exchange, long, maker) = setup();
+    exchange.set_fee_schedule("BTC-PERP".to_string(), FeeSchedule::flat(decimal("0.0002"), decimal("0.0005"))).unwrap();
+    exchange.place_order(order(&exchange, maker, Side::Sell, 100, 2, None)).unwrap();
+    exchange.place_order(order(&exchange, long, Side::Buy, 100, 2, Some(10))).unwrap();
+
+    clock.advance(Duration::hours(8));
+    mark(&mut exchange, 100);
+    exchange.run_funding().unwrap();
+
+    let position = exchange.get_account(long).unwrap().positions["BTC-PERP"].clone();
+    assert_eq!(position.fees, decimal("0.1"));
+    // 1 bp of the 400 notional paid to the short
+    assert_eq!(position.funding, decimal("-0.04"));
+    assert_eq!(exchange.get_account(maker).unwrap().positions["BTC-PERP"].funding, decimal("0.04"));
+}
+
+#[test]
+fn account_pnl_explains_the_change_in_equity() {
+    let (clock, mut exchange, long, maker) = setup();
+    exchange.set_fee_schedule("BTC-PERP".to_string(), FeeSchedule::flat(decimal("0.0002"), decimal("0.0005"))).unwrap();
+    exchange.place_order(order(&exchange, maker, Side::Buy, 96, 1, None)).unwrap();
+    exchange.place_order(order(&exchange, long, Side::Sell, 96, 1, Some(10))).unwrap();
+    clock.advance(Duration::hours(8));
+    mark(&mut exchange, 97);
+    exchange.run_funding().unwrap();
+
+    for user_id in [long, maker] {
+        let pnl = exchange.account_pnl(user_id).unwrap();
+        assert_eq!(&pnl.net, &(&pnl.realized_pnl + &pnl.unrealized_pnl + &pnl.funding - &pnl.fees));
+        let balance = exchange.get_account(user_id).unwrap().get_balance("USDT");
+        assert_eq!(balance + &pnl.unrealized_pnl - BigDecimal::from(1_000), pnl.net);
+    }
+    let pnl = exchange.account_pnl(long).unwrap();
+    assert_eq!((pnl.realized_pnl, pnl.unrealized_pnl), (BigDecimal::from(-4), BigDecimal::from(-3)));
+}
+
+#[test]
+fn selling_through_the_long_leaves_a_short() {
+    let (_, mut exchange, long, maker) = setup();
+    exchange.place_order(order(&exchange, maker, Side::Buy, 100, 5, None)).unwrap();
+    exchange.place_order(order(&exchange, long, Side::Sell, 100, 5, Some(10))).unwrap();
+    mark(&mut exchange, 90);
+
+    let position = exchange.get_account(long).unwrap().positions["BTC-PERP"].clone();
+    assert_eq!((position.side, position.quantity), (Side::Sell, BigDecimal::from(3)));
+    assert_eq!(position.unrealized_pnl, BigDecimal::from(30));
+    assert!(position.liquidation_price.unwrap() > BigDecimal::from(100));
+}
+
+#[test]
+fn closed_position_reopens_on_the_other_side() {
+    let (_, mut exchange, long, maker) = setup();
+    exchange.place_order(order(&exchange, maker, Side::Buy, 100, 2, None)).unwrap();
+    exchange.place_order(order(&exchange, long, Side::Sell, 100, 2, Some(10))).unwrap();
+    exchange.place_order(order(&exchange, maker, Side::Buy, 100, 1, None)).unwrap();
+    exchange.place_order(order(&exchange, long, Side::Sell, 100, 1, Some(10))).unwrap();
+    mark(&mut exchange, 90);
+
+    let position = exchange.get_account(long).unwrap().positions["BTC-PERP"].clone();
+    assert_eq!((position.side, position.quantity), (Side::Sell, BigDecimal::from(1)));
+    assert_eq!(position.unrealized_pnl, BigDecimal::from(10));
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use bigdecimal::BigDecimal;
+use common::{funded, order};
+use order_book::exchange::Exchange;
+use order_book::models::{Order, OrderError, Side, TimeInForce};
+use uuid::Uuid;
+
+fn reduce_only(exchange: &Exchange, user_id: Uuid, side: Side, price: i64, quantity: i64) -> Order {
+    let mut order = order(exchange, user_id, side, price, quantity, Some(10));
+    order.reduce_only = true;
+    order
+}
+
+fn position(exchange: &mut Exchange, user_id: Uuid) -> (Side, BigDecimal) {
+    exchange.get_account(user_id).unwrap().positions.get("BTC-PERP")
+        .map(|position| (position.side, position.quantity.clone()))
+        .unwrap_or((Side::Buy, BigDecimal::from(0)))
+}
+
+/**
+ * trader opens a long of 5 @ 100 against the maker, buyer has no position
+ */
+fn setup() -> (Exchange, Uuid, Uuid, Uuid) {
+    let (_, mut exchange, [trader, maker, buyer]) = funded([10_000, 10_000, 10_000]);
+    exchange.place_order(order(&exchange, maker, Side::Sell, 100, 5, Some(10))).unwrap();
+    exchange.place_order(order(&exchange, trader, Side::Buy, 100, 5, Some(10))).unwrap();
+    (exchange, trader, maker, buyer)
+}
+
+#[test]
+fn reduce_only_without_a_position_to_close_is_rejected() {
+    let (mut exchange, _, _, buyer) = setup();
+    let result = exchange.place_order(reduce_only(&exchange, buyer, Side::Sell, 101, 1));
+    assert!(matches!(result, Err(OrderError::ReduceOnlyWouldIncrease)));
+
+    // the same side as the position would add to it
+    let (mut exchange, trader, _, _) = setup();
+    let result = exchange.place_order(reduce_only(&exchange, trader, Side::Buy, 99, 1));
+    assert!(matches!(result, Err(OrderError::ReduceOnlyWouldIncrease)));
+}
+
+#[test]
+fn reduce_only_is_capped_to_the_position() {
+    let (mut exchange, trader, _, _) = setup();
+    let close = reduce_only(&exchange, trader, Side::Sell, 101, 8);
+    let close_id = close.id;
+    exchange.place_order(close).unwrap();
+
+    assert_eq!(exchange.get_order("BTC-PERP", close_id).unwrap().quantity, BigDecimal::from(5));
+}
+
+#[test]
+fn sweeping_two_reduce_only_orders_never_flips_the_position() {
+    let (mut exchange, trader, maker, buyer) = setup();
+    let (first, second) = (reduce_only(&exchange, trader, Side::Sell, 101, 5), reduce_only(&exchange, trader, Side::Sell, 101, 5));
+    let second_id = second.id;
+    exchange.place_order(first).unwrap();
+    exchange.place_order(second).unwrap();
+
+    let result = exchange.place_order(order(&exchange, buyer, Side::Buy, 101, 10, Some(10))).unwrap();
+
+    // the second order had nothing left to close once the first filled, and its cancel is reported
+    assert_eq!(result.trades.iter().map(|trade| trade.quantity.clone()).sum::<BigDecimal>(), BigDecimal::from(5));
+    assert!(exchange.get_order("BTC-PERP", second_id).is_none());
+    assert_eq!(result.cancelled.iter().map(|order| order.id).collect::<Vec<_>>(), vec![second_id]);
+    assert_eq!(result.cancelled[0].sequence, result.trades[0].sequence + 1);
+    assert_eq!(position(&mut exchange, trader).1, BigDecimal::from(0));
+    assert_eq!(position(&mut exchange, buyer), (Side::Buy, BigDecimal::from(5)));
+    assert_eq!(position(&mut exchange, maker), (Side::Sell, BigDecimal::from(5)));
+    assert_eq!(exchange.get_account(trader).unwrap().held_margin("USDT"), BigDecimal::from(0));
+}
+
+#[test]
+fn other_fills_in_the_same_sweep_use_up_the_reduce_only_order() {
+    let (mut exchange, trader, _, buyer) = setup();
+    exchange.place_order(order(&exchange, trader, Side::Sell, 101, 3, Some(10))).unwrap();
+    let close = reduce_only(&exchange, trader, Side::Sell, 102, 5);
+    let close_id = close.id;
+    exchange.place_order(close).unwrap();
+
+    let result = exchange.place_order(order(&exchange, buyer, Side::Buy, 102, 10, Some(10))).unwrap();
+
+    // 3 from the plain sell, then only the 2 still open on the position
+    let fills: Vec<BigDecimal> = result.trades.iter().map(|trade| trade.quantity.clone()).collect();
+    assert_eq!(fills, vec![BigDecimal::from(3), BigDecimal::from(2)]);
+    assert!(exchange.get_order("BTC-PERP", close_id).is_none());
+    assert_eq!(position(&mut exchange, trader).1, BigDecimal::from(0));
+}
+
+#[test]
+fn resting_reduce_only_is_trimmed_when_the_position_shrinks() {
+    let (mut exchange, trader, maker, _) = setup();
+    let close = reduce_only(&exchange, trader, Side::Sell, 105, 5);
+    let close_id = close.id;
+    exchange.place_order(close).unwrap();
+
+    exchange.place_order(order(&exchange, maker, Side::Buy, 99, 3, Some(10))).unwrap();
+    exchange.place_order(order(&exchange, trader, Side::Sell, 99, 3, Some(10))).unwrap();
+
+    assert_eq!(exchange.get_order("BTC-PERP", close_id).unwrap().remaining_quantity(), BigDecimal::from(2));
+}
+
+#[test]
+fn fok_does_not_count_what_a_reduce_only_order_cannot_fill() {
+    let (mut exchange, trader, _, buyer) = setup();
+    exchange.place_order(order(&exchange, trader, Side::Sell, 100, 5, Some(10))).unwrap();
+    let close = reduce_only(&exchange, trader, Side::Sell, 101, 5);
+    let close_id = close.id;
+    exchange.place_order(close).unwrap();
+
+    let mut fok = order(&exchange, buyer, Side::Buy, 101, 10, Some(10));
+    fok.time_in_force = TimeInForce::FOK;
+    let result = exchange.place_order(fok).unwrap();
+
+    // the plain sell would use up the whole position, leaving the reduce-only order nothing to fill
+    assert!(result.trades.is_empty());
+    assert_eq!(exchange.get_order("BTC-PERP", close_id).unwrap().remaining_quantity(), BigDecimal::from(5));
+}
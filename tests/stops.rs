@@ -0,0 +1,98 @@
+mod common;
+
+use bigdecimal::BigDecimal;
+use common::{funded, mark, order};
+use order_book::exchange::Exchange;
+use order_book::models::{Order, OrderType, Side, TriggerBy};
+use uuid::Uuid;
+
+/**
+ * market stop for quantity that fires once the reference crosses trigger
+ */
+fn stop(exchange: &Exchange, user_id: Uuid, side: Side, trigger: i64, trigger_by: TriggerBy, quantity: i64) -> Order {
+    let mut stop = order(exchange, user_id, side, trigger, quantity, Some(10));
+    stop.order_type = OrderType::Stop;
+    stop.trigger_price = Some(BigDecimal::from(trigger));
+    stop.trigger_by = trigger_by;
+    stop
+}
+
+/**
+ * trader long quantity @ 100 from the maker, with the maker bidding 5 @ 95 to close into
+ */
+fn long_with_bids(exchange: &mut Exchange, trader: Uuid, maker: Uuid, quantity: i64) {
+    exchange.place_order(order(exchange, maker, Side::Sell, 100, quantity, None)).unwrap();
+    exchange.place_order(order(exchange, trader, Side::Buy, 100, quantity, Some(10))).unwrap();
+    exchange.place_order(order(exchange, maker, Side::Buy, 95, 5, None)).unwrap();
+}
+
+fn position(exchange: &mut Exchange, user_id: Uuid) -> BigDecimal {
+    exchange.get_account(user_id).unwrap().positions["BTC-PERP"].quantity.clone()
+}
+
+#[test]
+fn trade_through_the_trigger_fires_a_last_price_stop() {
+    let (_, mut exchange, [trader, maker, taker]) = funded([1_000, 1_000, 1_000]);
+    let stop = stop(&exchange, trader, Side::Buy, 105, TriggerBy::LastPrice, 1);
+    let stop_id = stop.id;
+    exchange.place_order(stop).unwrap();
+    exchange.place_order(order(&exchange, maker, Side::Sell, 105, 3, None)).unwrap();
+
+    let result = exchange.place_order(order(&exchange, taker, Side::Buy, 105, 1, None)).unwrap();
+    assert_eq!(result.trades.len(), 2);
+    assert_eq!(result.trades[1].buyer_user_id, trader);
+    assert_eq!(position(&mut exchange, trader), BigDecimal::from(1));
+    assert!(exchange.get_order("BTC-PERP", stop_id).is_none());
+}
+
+#[test]
+fn mark_through_the_trigger_fires_a_mark_price_stop() {
+    let (_, mut exchange, [trader, maker]) = funded([1_000, 1_000]);
+    exchange.place_order(stop(&exchange, trader, Side::Sell, 95, TriggerBy::MarkPrice, 1)).unwrap();
+    exchange.place_order(order(&exchange, maker, Side::Buy, 94, 1, None)).unwrap();
+    assert!(mark(&mut exchange, 96).trades.is_empty());
+
+    let result = mark(&mut exchange, 95);
+    assert_eq!(result.trades.len(), 1);
+    assert_eq!((result.trades[0].seller_user_id, result.trades[0].price.clone()), (trader, BigDecimal::from(94)));
+    assert_eq!(exchange.get_account(trader).unwrap().positions["BTC-PERP"].side, Side::Sell);
+}
+
+#[test]
+fn reduce_only_stop_is_capped_to_what_is_left_when_it_fires() {
+    let (_, mut exchange, [trader, maker]) = funded([1_000, 1_000]);
+    long_with_bids(&mut exchange, trader, maker, 3);
+    for _ in 0..2 {
+        let mut close = stop(&exchange, trader, Side::Sell, 95, TriggerBy::MarkPrice, 2);
+        close.reduce_only = true;
+        exchange.place_order(close).unwrap();
+    }
+
+    // the first stop closes 2 of the 3, so the second can only close the last one
+    let result = mark(&mut exchange, 95);
+    let filled: Vec<BigDecimal> = result.trades.iter().map(|trade| trade.quantity.clone()).collect();
+    assert_eq!(filled, vec![BigDecimal::from(2), BigDecimal::from(1)]);
+    assert_eq!(position(&mut exchange, trader), BigDecimal::from(0));
+    assert_eq!(exchange.get_account(trader).unwrap().held_margin("USDT"), BigDecimal::from(0));
+}
+
+#[test]
+fn reduce_only_stop_with_nothing_left_to_close_is_dropped_with_its_hold() {
+    let (_, mut exchange, [trader, maker]) = funded([1_000, 1_000]);
+    long_with_bids(&mut exchange, trader, maker, 2);
+    let mut stop_ids = Vec::new();
+    for _ in 0..2 {
+        let mut close = stop(&exchange, trader, Side::Sell, 95, TriggerBy::MarkPrice, 2);
+        close.reduce_only = true;
+        stop_ids.push(close.id);
+        exchange.place_order(close).unwrap();
+    }
+    assert_eq!(exchange.get_account(trader).unwrap().held_margin("USDT"), BigDecimal::from(38));
+
+    let result = mark(&mut exchange, 95);
+    assert_eq!(result.trades.len(), 1);
+    assert_eq!(position(&mut exchange, trader), BigDecimal::from(0));
+    assert!(stop_ids.iter().all(|stop_id| exchange.get_order("BTC-PERP", *stop_id).is_none()));
+    assert_eq!(exchange.get_account(trader).unwrap().held_margin("USDT"), BigDecimal::from(0));
+    assert_eq!(exchange.available_balance(trader, "USDT").unwrap(), exchange.get_account(trader).unwrap().get_balance("USDT"));
+}
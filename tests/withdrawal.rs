@@ -0,0 +1,87 @@
+mod common;
+
+use bigdecimal::BigDecimal;
+use chrono::Duration;
+use common::{funded, mark, order};
+use order_book::clock::Clock;
+use order_book::models::{OrderError, Side};
+use order_book::withdrawal::WithdrawalStatus;
+use uuid::Uuid;
+
+#[test]
+fn available_balance_leaves_out_margin_orders_and_unrealized_losses() {
+    let (_, mut exchange, [trader, maker]) = funded([1_000, 1_000]);
+    exchange.place_order(order(&exchange, maker, Side::Sell, 100, 2, None)).unwrap();
+    exchange.place_order(order(&exchange, trader, Side::Buy, 100, 2, Some(10))).unwrap();
+    exchange.place_order(order(&exchange, trader, Side::Buy, 80, 1, Some(10))).unwrap();
+    mark(&mut exchange, 95);
+
+    // 20 of position margin, 8 held for the resting bid, 10 of unrealized loss
+    assert_eq!(exchange.available_balance(trader, "USDT").unwrap(), BigDecimal::from(962));
+    assert!(matches!(
+        exchange.request_withdrawal(trader, "USDT".to_string(), BigDecimal::from(963)),
+        Err(OrderError::InsufficientBalance),
+    ));
+    assert!(exchange.request_withdrawal(trader, "USDT".to_string(), BigDecimal::from(962)).is_ok());
+    assert_eq!(exchange.available_balance(trader, "USDT").unwrap(), BigDecimal::from(0));
+}
+
+#[test]
+fn request_takes_the_amount_off_the_balance() {
+    let (_, mut exchange, [trader, _]) = funded([1_000, 1_000]);
+    let id = exchange.request_withdrawal(trader, "USDT".to_string(), BigDecimal::from(300)).unwrap();
+
+    assert_eq!(exchange.get_account(trader).unwrap().get_balance("USDT"), BigDecimal::from(700));
+    assert_eq!(exchange.get_withdrawal(id).unwrap().status, WithdrawalStatus::Pending);
+    // a pending withdrawal cannot be spent twice
+    assert!(exchange.request_withdrawal(trader, "USDT".to_string(), BigDecimal::from(701)).is_err());
+    assert!(exchange.request_withdrawal(trader, "USDT".to_string(), BigDecimal::from(0)).is_err());
+}
+
+#[test]
+fn rejection_returns_the_funds() {
+    let (_, mut exchange, [trader, _]) = funded([1_000, 1_000]);
+    let id = exchange.request_withdrawal(trader, "USDT".to_string(), BigDecimal::from(300)).unwrap();
+    exchange.approve_withdrawal(id).unwrap();
+    exchange.reject_withdrawal(id, "address failed screening".to_string()).unwrap();
+
+    assert_eq!(exchange.get_account(trader).unwrap().get_balance("USDT"), BigDecimal::from(1_000));
+    let withdrawal = exchange.get_withdrawal(id).unwrap();
+    assert_eq!(withdrawal.status, WithdrawalStatus::Rejected);
+    assert_eq!(withdrawal.history.last().unwrap().note.as_deref(), Some("address failed screening"));
+}
+
+#[test]
+fn completed_withdrawal_keeps_every_step() {
+    let (clock, mut exchange, [trader, _]) = funded([1_000, 1_000]);
+    let requested_at = clock.now();
+    let id = exchange.request_withdrawal(trader, "USDT".to_string(), BigDecimal::from(100)).unwrap();
+    clock.advance(Duration::minutes(5));
+    exchange.approve_withdrawal(id).unwrap();
+    clock.advance(Duration::minutes(5));
+    exchange.complete_withdrawal(id).unwrap();
+
+    let steps: Vec<_> = exchange.get_withdrawal(id).unwrap().history.iter()
+        .map(|event| (event.status, event.at))
+        .collect();
+    assert_eq!(steps, vec![
+        (WithdrawalStatus::Pending, requested_at),
+        (WithdrawalStatus::Approved, requested_at + Duration::minutes(5)),
+        (WithdrawalStatus::Completed, requested_at + Duration::minutes(10)),
+    ]);
+    assert_eq!(exchange.get_account(trader).unwrap().get_balance("USDT"), BigDecimal::from(900));
+    assert_eq!(exchange.get_withdrawals(trader).len(), 1);
+}
+
+#[test]
+fn finished_withdrawals_cannot_move_again() {
+    let (_, mut exchange, [trader, _]) = funded([1_000, 1_000]);
+    let id = exchange.request_withdrawal(trader, "USDT".to_string(), BigDecimal::from(100)).unwrap();
+    assert!(matches!(exchange.complete_withdrawal(id), Err(OrderError::InvalidWithdrawalTransition)));
+
+    exchange.approve_withdrawal(id).unwrap();
+    exchange.complete_withdrawal(id).unwrap();
+    assert!(matches!(exchange.reject_withdrawal(id, "too late".to_string()), Err(OrderError::InvalidWithdrawalTransition)));
+    assert_eq!(exchange.get_account(trader).unwrap().get_balance("USDT"), BigDecimal::from(900));
+    assert!(matches!(exchange.approve_withdrawal(Uuid::new_v4()), Err(OrderError::WithdrawalNotFound)));
+}